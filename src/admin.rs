@@ -0,0 +1,167 @@
+use jsonrpsee::{core::RpcResult, proc_macros::rpc};
+use reth_primitives::{BlockNumberOrTag, TransactionVariant, B256};
+use reth_provider::{BlockIdReader, BlockReader, ChainSpecProvider, StateProviderFactory};
+use std::sync::Arc;
+use tracing::info;
+
+use super::{
+    db::Database,
+    error::ZeroApiError,
+    rpc::block_number,
+    tracer::{retrace_block, verify_block_trace, TracerConfig},
+};
+
+/// The maximum number of blocks retraced by a single `retraceRange` call.
+const MAX_RETRACE_RANGE: u64 = 1000;
+
+/// trait interface for zero tracer admin rpc.
+///
+/// This defines the zeroadmin namespace, which is only served on the IPC and
+/// auth endpoints.
+#[rpc(server, client, namespace = "zeroadmin")]
+pub trait ZeroAdminRpcApi {
+    /// Re-execute and trace a canonical block, replacing its stored trace.
+    #[method(name = "retraceBlock")]
+    async fn retrace_block(&self, number: u64) -> RpcResult<B256>;
+
+    /// Retrace the canonical blocks `from..=to`, returning the number of
+    /// retraced blocks.
+    #[method(name = "retraceRange")]
+    async fn retrace_range(&self, from: u64, to: u64) -> RpcResult<u64>;
+
+    /// Delete the trace of a block.
+    #[method(name = "deleteTrace")]
+    async fn delete_trace(&self, hash: B256) -> RpcResult<()>;
+
+    /// Delete the traces of all blocks below `before`, returning the number of
+    /// deleted traces.
+    #[method(name = "prune")]
+    async fn prune(&self, before: u64) -> RpcResult<u64>;
+
+    /// Re-execute a block using only its stored block trace witness.
+    #[method(name = "verifyBlockTrace")]
+    async fn verify_block_trace(&self, number: BlockNumberOrTag) -> RpcResult<()>;
+}
+
+/// Zero Tracer admin RPC implementation.
+#[derive(Clone)]
+pub struct ZeroAdminRpc<Provider> {
+    /// Database instance.
+    db: Arc<dyn Database>,
+    /// Blockchain data provider.
+    provider: Provider,
+    /// The configuration used for retracing.
+    config: TracerConfig,
+}
+
+impl<Provider> ZeroAdminRpc<Provider> {
+    /// Construct a new ZeroAdminRpc instance.
+    pub fn new(db: Arc<dyn Database>, provider: Provider, config: TracerConfig) -> Self {
+        Self {
+            db,
+            provider,
+            config,
+        }
+    }
+}
+
+impl<Provider> ZeroAdminRpc<Provider>
+where
+    Provider:
+        BlockReader + BlockIdReader + StateProviderFactory + ChainSpecProvider + Clone + 'static,
+{
+    /// Retrace a block and replace its stored trace.
+    ///
+    /// The new trace is committed over the stored one, so the block keeps a
+    /// trace if the commit fails. A stored trace of a block which is no longer
+    /// canonical is deleted afterwards.
+    async fn retrace(&self, number: u64) -> Result<B256, ZeroApiError> {
+        let provider = self.provider.clone();
+        let config = self.config;
+        let (block_hash, block_trace) =
+            tokio::task::spawn_blocking(move || retrace_block(&provider, &config, number))
+                .await
+                .map_err(|err| ZeroApiError::TaskFailed(err.to_string()))?
+                .map_err(|err| ZeroApiError::RetraceFailed(number, err.to_string()))?;
+
+        let stored_hash = self.db.get_block_hash_by_number(number).await?;
+        self.db
+            .commit_block_trace(block_hash, number, block_trace)
+            .await?;
+        if let Some(stored_hash) = stored_hash.filter(|stored_hash| *stored_hash != block_hash) {
+            self.db.delete_block_trace_by_hash(stored_hash).await?;
+        }
+        info!("Retraced block {} - {}", number, block_hash);
+        Ok(block_hash)
+    }
+}
+
+#[async_trait::async_trait]
+impl<Provider> ZeroAdminRpcApiServer for ZeroAdminRpc<Provider>
+where
+    Provider:
+        BlockReader + BlockIdReader + StateProviderFactory + ChainSpecProvider + Clone + 'static,
+{
+    async fn retrace_block(&self, number: u64) -> RpcResult<B256> {
+        Ok(self.retrace(number).await?)
+    }
+
+    async fn retrace_range(&self, from: u64, to: u64) -> RpcResult<u64> {
+        if to < from || to - from >= MAX_RETRACE_RANGE {
+            return Err(ZeroApiError::InvalidRange(from, to).into());
+        }
+        for number in from..=to {
+            self.retrace(number).await?;
+        }
+        Ok(to - from + 1)
+    }
+
+    async fn delete_trace(&self, hash: B256) -> RpcResult<()> {
+        self.db
+            .delete_block_trace_by_hash(hash)
+            .await
+            .map_err(ZeroApiError::DatabaseError)?;
+        info!("Deleted trace of block {}", hash);
+        Ok(())
+    }
+
+    async fn prune(&self, before: u64) -> RpcResult<u64> {
+        let pruned = self
+            .db
+            .delete_block_traces_before(before)
+            .await
+            .map_err(ZeroApiError::DatabaseError)?;
+        info!("Pruned {} traces before block {}", pruned, before);
+        Ok(pruned)
+    }
+
+    async fn verify_block_trace(&self, number: BlockNumberOrTag) -> RpcResult<()> {
+        let number = block_number(&self.provider, number)?;
+        let block_trace = self
+            .db
+            .get_block_trace_by_number(number)
+            .await
+            .map_err(ZeroApiError::DatabaseError)?
+            .ok_or(ZeroApiError::TraceNotFoundForBlockNumber(number))?;
+        let block = self
+            .provider
+            .sealed_block_with_senders(number.into(), TransactionVariant::WithHash)
+            .map_err(ZeroApiError::ProviderError)?
+            .ok_or(ZeroApiError::BlockNotFound(number))?;
+        let receipts = self
+            .provider
+            .receipts_by_block(number.into())
+            .map_err(ZeroApiError::ProviderError)?
+            .ok_or(ZeroApiError::BlockNotFound(number))?;
+
+        let provider = self.provider.clone();
+        tokio::task::spawn_blocking(move || {
+            verify_block_trace(&provider, &block, &receipts, &block_trace)
+        })
+        .await
+        .map_err(|err| ZeroApiError::TaskFailed(err.to_string()))?
+        .map_err(ZeroApiError::VerificationFailed)?;
+        info!("Verified trace of block {}", number);
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+//! Export and import of block traces as newline-delimited JSON.
+//!
+//! Each line of an archive holds the block number, hash and trace of one
+//! block. Archives with a `.zst` extension are compressed with zstd.
+use clap::Subcommand;
+use eyre::eyre;
+use revm::primitives::FixedBytes;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+use trace_decoder::BlockTrace;
+
+use super::db::{self, Database, DatabaseConfig, DatabaseType};
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum DbCommand {
+    /// Export a block range of traces into an archive
+    Export(ExportCommand),
+    /// Import the traces of an archive
+    Import(ImportCommand),
+}
+
+impl DbCommand {
+    pub(crate) async fn execute(self) -> eyre::Result<()> {
+        match self {
+            DbCommand::Export(command) => command.execute().await,
+            DbCommand::Import(command) => command.execute().await,
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct DatabaseArgs {
+    /// The database type to read from or write to
+    #[arg(long = "db-type", value_name = "DATABASE_TYPE")]
+    db_type: DatabaseType,
+    /// The path / url to the database
+    #[arg(long = "db-path", value_name = "DATABASE_PATH")]
+    db_path: String,
+}
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct ExportCommand {
+    #[command(flatten)]
+    database: DatabaseArgs,
+    /// The first block to export
+    #[arg(long = "from")]
+    from: u64,
+    /// The last block to export, defaults to the first block
+    #[arg(long = "to")]
+    to: Option<u64>,
+    /// The archive to write
+    #[arg(long = "output")]
+    output: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct ImportCommand {
+    #[command(flatten)]
+    database: DatabaseArgs,
+    /// The archive to read
+    #[arg(long = "input")]
+    input: PathBuf,
+}
+
+/// A single line of an archive.
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchiveEntry {
+    block_number: u64,
+    block_hash: FixedBytes<32>,
+    block_trace: BlockTrace,
+}
+
+impl ExportCommand {
+    async fn execute(self) -> eyre::Result<()> {
+        // the source is only read, and must not be created if it is missing
+        let db = db::open_read_only(
+            self.database.db_type,
+            &self.database.db_path,
+            &DatabaseConfig::default(),
+        )
+        .await?;
+        let (exported, missing) = export(
+            db.as_ref(),
+            self.from..=self.to.unwrap_or(self.from),
+            &self.output,
+        )
+        .await?;
+
+        println!("Exported {exported} block traces, {missing} blocks have no trace");
+        Ok(())
+    }
+}
+
+impl ImportCommand {
+    async fn execute(self) -> eyre::Result<()> {
+        let db = db::open(
+            self.database.db_type,
+            &self.database.db_path,
+            &DatabaseConfig::default(),
+        )
+        .await?;
+        let imported = import(db.as_ref(), &self.input).await?;
+
+        println!("Imported {imported} block traces");
+        Ok(())
+    }
+}
+
+/// Write the traces of a block range to an archive, returning the number of
+/// exported traces and of blocks without a trace.
+async fn export(
+    db: &dyn Database,
+    blocks: RangeInclusive<u64>,
+    output: &Path,
+) -> eyre::Result<(u64, u64)> {
+    let mut writer = archive_writer(output)?;
+
+    let (mut exported, mut missing) = (0, 0);
+    for block_number in blocks {
+        let Some(block_hash) = db.get_block_hash_by_number(block_number).await? else {
+            missing += 1;
+            continue;
+        };
+        let block_trace = db
+            .get_block_trace_by_hash(block_hash)
+            .await?
+            .ok_or_else(|| eyre!("trace of block {block_number} disappeared during export"))?;
+
+        let entry = ArchiveEntry {
+            block_number,
+            block_hash,
+            block_trace,
+        };
+        serde_json::to_writer(&mut writer, &entry)?;
+        writer.write_all(b"\n")?;
+        exported += 1;
+    }
+    writer.flush()?;
+    Ok((exported, missing))
+}
+
+/// Commit the traces of an archive which are not stored yet, returning the
+/// number of imported traces.
+async fn import(db: &dyn Database, input: &Path) -> eyre::Result<u64> {
+    let mut imported = 0;
+    for line in archive_reader(input)?.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry: ArchiveEntry = serde_json::from_str(&line)?;
+        if db.get_block_hash_by_number(entry.block_number).await? == Some(entry.block_hash) {
+            continue;
+        }
+        db.commit_block_trace(entry.block_hash, entry.block_number, entry.block_trace)
+            .await?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "zst")
+}
+
+fn archive_writer(path: &Path) -> eyre::Result<Box<dyn Write>> {
+    let file = BufWriter::new(File::create(path)?);
+    Ok(match is_compressed(path) {
+        true => Box::new(zstd::Encoder::new(file, 0)?.auto_finish()),
+        false => Box::new(file),
+    })
+}
+
+fn archive_reader(path: &Path) -> eyre::Result<BufReader<Box<dyn Read>>> {
+    let file = File::open(path)?;
+    Ok(BufReader::new(match is_compressed(path) {
+        true => Box::new(zstd::Decoder::new(file)?),
+        false => Box::new(file),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpt_trie::partial_trie::HashedPartialTrie;
+    use std::collections::HashMap;
+    use trace_decoder::{
+        BlockTraceTriePreImages, SeparateStorageTriesPreImage, SeparateTriePreImage,
+        SeparateTriePreImages,
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("zeth-archive-{}-{name}", std::process::id()))
+    }
+
+    fn block_trace() -> BlockTrace {
+        BlockTrace {
+            trie_pre_images: BlockTraceTriePreImages::Separate(SeparateTriePreImages {
+                state: SeparateTriePreImage::Direct(HashedPartialTrie::default()),
+                storage: SeparateStorageTriesPreImage::MultipleTries(HashMap::new()),
+            }),
+            code_db: Some(HashMap::new()),
+            txn_info: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn exported_traces_import_unchanged() {
+        let (source_dir, target_dir, archive) = (
+            temp_path("source"),
+            temp_path("target"),
+            temp_path("traces.jsonl.zst"),
+        );
+        let source = db::Filesystem::new(source_dir.to_str().unwrap(), false)
+            .await
+            .expect("source is created");
+        let block_hash = FixedBytes::repeat_byte(0x01);
+        source
+            .commit_block_trace(block_hash, 2, block_trace())
+            .await
+            .expect("trace is stored");
+
+        let source = db::open_read_only(
+            DatabaseType::Filesystem,
+            source_dir.to_str().unwrap(),
+            &DatabaseConfig::default(),
+        )
+        .await
+        .expect("source exists");
+        let exported = export(source.as_ref(), 1..=2, &archive).await;
+        assert_eq!(exported.expect("traces are exported"), (1, 1));
+
+        let target = db::Filesystem::new(target_dir.to_str().unwrap(), false)
+            .await
+            .expect("target is created");
+        assert_eq!(import(&target, &archive).await.expect("archive imports"), 1);
+        // traces already stored are skipped
+        assert_eq!(import(&target, &archive).await.expect("archive imports"), 0);
+
+        let imported = target
+            .get_block_trace_by_number(2)
+            .await
+            .expect("trace is read")
+            .expect("trace is imported");
+        assert_eq!(
+            serde_json::to_value(imported).unwrap(),
+            serde_json::to_value(block_trace()).unwrap()
+        );
+
+        for dir in [source_dir, target_dir] {
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_export_source_is_not_created() {
+        let dir = temp_path("missing");
+        let source = db::open_read_only(
+            DatabaseType::Filesystem,
+            dir.to_str().unwrap(),
+            &DatabaseConfig::default(),
+        )
+        .await;
+
+        assert!(source.is_err());
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,79 @@
+//! Zeth commands which run without launching a node.
+use clap::{CommandFactory, Parser, Subcommand};
+use reth_ipc::client::IpcClientBuilder;
+use reth_primitives::BlockNumberOrTag;
+
+use super::{admin::ZeroAdminRpcApiClient, archive::DbCommand, diff::DiffTraceCommand};
+
+/// Zeth specific commands.
+#[derive(Debug, Parser)]
+#[command(name = "zeth")]
+pub(crate) struct ZethCli {
+    #[command(subcommand)]
+    command: ZethCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum ZethCommand {
+    /// Compare zero traces against reth's prestate tracer
+    DiffTrace(DiffTraceCommand),
+    /// Re-execute a block using only its stored witness
+    Verify(VerifyCommand),
+    /// Move block traces between databases
+    #[command(subcommand)]
+    Db(DbCommand),
+}
+
+#[derive(Debug, clap::Args)]
+struct VerifyCommand {
+    /// The IPC endpoint of the zeth node, verification is only served over IPC
+    #[arg(long = "ipc-path", default_value = "/tmp/reth.ipc")]
+    ipc_path: String,
+    /// The block to verify
+    #[arg(long = "block")]
+    block: u64,
+}
+
+impl VerifyCommand {
+    async fn execute(self) -> eyre::Result<()> {
+        let client = IpcClientBuilder::default().build(&self.ipc_path).await?;
+        client
+            .verify_block_trace(BlockNumberOrTag::Number(self.block))
+            .await?;
+        println!("Block {}: witness verified", self.block);
+        Ok(())
+    }
+}
+
+impl ZethCli {
+    /// Returns true if the command line arguments select a zeth command rather
+    /// than a reth command. Zeth commands may share a prefix with reth
+    /// commands, e.g. `db export` and `db stats`.
+    pub(crate) fn is_selected() -> bool {
+        let mut command = Self::command();
+        for arg in std::env::args().skip(1) {
+            if arg.starts_with('-') {
+                break;
+            }
+            match command.find_subcommand(&arg) {
+                Some(subcommand) => command = subcommand.clone(),
+                None => break,
+            }
+        }
+        command.get_name() != "zeth" && !command.has_subcommands()
+    }
+
+    /// Execute the selected zeth command.
+    pub(crate) fn run(self) -> eyre::Result<()> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(async move {
+            match self.command {
+                ZethCommand::DiffTrace(command) => command.execute().await,
+                ZethCommand::Verify(command) => command.execute().await,
+                ZethCommand::Db(command) => command.execute().await,
+            }
+        })
+    }
+}
@@ -0,0 +1,538 @@
+use revm::primitives::FixedBytes;
+use serde_json::value::RawValue;
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+use trace_decoder::BlockTrace;
+
+use super::{raw, Database, DatabaseError, TraceDownload};
+
+/// A database wrapper caching decoded block traces.
+///
+/// The cache holds the most recently used traces up to a total size of
+/// `capacity` bytes, measured as the length of their JSON encoding. Hits and
+/// misses are reported as `zeth_trace_cache_*` metrics.
+///
+/// Commits and deletes invalidate the cache once the inner database has been
+/// updated, and bump a generation counter so that reads which started before
+/// the write do not cache the trace they fetched.
+pub struct Cached {
+    inner: Arc<dyn Database>,
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<FixedBytes<32>, CacheEntry>,
+    by_number: HashMap<u64, FixedBytes<32>>,
+    size: usize,
+    tick: u64,
+    /// Incremented by every invalidation.
+    generation: u64,
+}
+
+struct CacheEntry {
+    block_number: Option<u64>,
+    block_trace: BlockTrace,
+    size: usize,
+    last_used: u64,
+}
+
+impl Cached {
+    pub fn new(inner: Arc<dyn Database>, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            state: Default::default(),
+        }
+    }
+
+    fn get(&self, block_hash: &FixedBytes<32>) -> Option<BlockTrace> {
+        let mut state = self.state.lock().expect("cache lock is not poisoned");
+        state.tick += 1;
+        let tick = state.tick;
+        let block_trace = state.entries.get_mut(block_hash).map(|entry| {
+            entry.last_used = tick;
+            entry.block_trace.clone()
+        });
+        drop(state);
+
+        match &block_trace {
+            Some(_) => metrics::counter!("zeth_trace_cache_hits").increment(1),
+            None => self.miss(),
+        }
+        block_trace
+    }
+
+    /// The generation to pass to [`Cached::insert`] for a read of the inner
+    /// database starting now.
+    fn generation(&self) -> u64 {
+        self.state
+            .lock()
+            .expect("cache lock is not poisoned")
+            .generation
+    }
+
+    /// Drop a trace, and any trace cached for its block number, after a write
+    /// to the inner database.
+    fn invalidate(&self, block_hash: &FixedBytes<32>, block_number: Option<u64>) {
+        let mut state = self.state.lock().expect("cache lock is not poisoned");
+        state.generation += 1;
+        state.remove(block_hash);
+        if let Some(block_number) = block_number {
+            if let Some(cached) = state.by_number.remove(&block_number) {
+                state.remove(&cached);
+            }
+        }
+        self.record(&state);
+    }
+
+    /// Drop all cached traces after a write to the inner database.
+    fn invalidate_all(&self) {
+        let mut state = self.state.lock().expect("cache lock is not poisoned");
+        let generation = state.generation + 1;
+        *state = CacheState {
+            generation,
+            ..Default::default()
+        };
+        self.record(&state);
+    }
+
+    fn miss(&self) {
+        metrics::counter!("zeth_trace_cache_misses").increment(1);
+    }
+
+    /// Cache a trace read from the inner database, unless the cache was
+    /// invalidated since `generation` was taken. `size` is the length of the
+    /// trace's JSON encoding.
+    fn insert(
+        &self,
+        generation: u64,
+        block_hash: FixedBytes<32>,
+        block_number: Option<u64>,
+        block_trace: &BlockTrace,
+        size: usize,
+    ) {
+        if size > self.capacity {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("cache lock is not poisoned");
+        if state.generation != generation {
+            return;
+        }
+        state.tick += 1;
+        let entry = CacheEntry {
+            block_number,
+            block_trace: block_trace.clone(),
+            size,
+            last_used: state.tick,
+        };
+        if let Some(previous) = state.entries.insert(block_hash, entry) {
+            state.size -= previous.size;
+        }
+        if let Some(block_number) = block_number {
+            state.by_number.insert(block_number, block_hash);
+        }
+        state.size += size;
+
+        // evict the least recently used traces
+        while state.size > self.capacity {
+            let Some(evicted) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(hash, _)| *hash)
+            else {
+                break;
+            };
+            state.remove(&evicted);
+        }
+        self.record(&state);
+    }
+
+    /// Cache a trace read raw from the inner database, decoding it once so
+    /// that decoded reads hit the cache as well.
+    fn insert_raw(
+        &self,
+        generation: u64,
+        block_hash: FixedBytes<32>,
+        block_number: Option<u64>,
+        raw: &RawValue,
+    ) -> Result<(), DatabaseError> {
+        let size = raw.get().len();
+        if size > self.capacity {
+            return Ok(());
+        }
+        let block_trace = serde_json::from_str(raw.get()).map_err(DatabaseError::MalformedTrace)?;
+        self.insert(generation, block_hash, block_number, &block_trace, size);
+        Ok(())
+    }
+
+    fn record(&self, state: &CacheState) {
+        metrics::gauge!("zeth_trace_cache_bytes").set(state.size as f64);
+        metrics::gauge!("zeth_trace_cache_entries").set(state.entries.len() as f64);
+    }
+}
+
+impl CacheState {
+    fn remove(&mut self, block_hash: &FixedBytes<32>) {
+        let Some(entry) = self.entries.remove(block_hash) else {
+            return;
+        };
+        self.size -= entry.size;
+        if let Some(block_number) = entry.block_number {
+            if self.by_number.get(&block_number) == Some(block_hash) {
+                self.by_number.remove(&block_number);
+            }
+        }
+    }
+}
+
+/// The length of the JSON encoding of a block trace, computed without
+/// buffering the encoding.
+fn encoded_size(block_trace: &BlockTrace) -> usize {
+    struct Counter(usize);
+
+    impl io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, block_trace).expect("block trace is serializable");
+    counter.0
+}
+
+#[async_trait::async_trait]
+impl Database for Cached {
+    async fn commit_block_trace(
+        &self,
+        block_hash: FixedBytes<32>,
+        block_number: u64,
+        block_trace: BlockTrace,
+    ) -> Result<(), DatabaseError> {
+        let result = self
+            .inner
+            .commit_block_trace(block_hash, block_number, block_trace)
+            .await;
+        // a replaced trace, or the trace of a reorged block, must not be served
+        self.invalidate(&block_hash, Some(block_number));
+        result
+    }
+
+    async fn get_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<BlockTrace>, DatabaseError> {
+        if let Some(block_trace) = self.get(&block_hash) {
+            return Ok(Some(block_trace));
+        }
+        let generation = self.generation();
+        let block_trace = self.inner.get_block_trace_by_hash(block_hash).await?;
+        if let Some(block_trace) = &block_trace {
+            let size = encoded_size(block_trace);
+            self.insert(generation, block_hash, None, block_trace, size);
+        }
+        Ok(block_trace)
+    }
+
+    async fn get_block_trace_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<BlockTrace>, DatabaseError> {
+        let cached = self
+            .state
+            .lock()
+            .expect("cache lock is not poisoned")
+            .by_number
+            .get(&block_number)
+            .copied();
+        match cached {
+            Some(block_hash) => {
+                if let Some(block_trace) = self.get(&block_hash) {
+                    return Ok(Some(block_trace));
+                }
+            }
+            None => self.miss(),
+        }
+
+        let generation = self.generation();
+        let Some(block_hash) = self.inner.get_block_hash_by_number(block_number).await? else {
+            return Ok(None);
+        };
+        let block_trace = self.inner.get_block_trace_by_hash(block_hash).await?;
+        if let Some(block_trace) = &block_trace {
+            let size = encoded_size(block_trace);
+            self.insert(
+                generation,
+                block_hash,
+                Some(block_number),
+                block_trace,
+                size,
+            );
+        }
+        Ok(block_trace)
+    }
+
+    /// Cached traces are encoded, all others are read raw from the inner
+    /// database and cached.
+    async fn get_raw_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        if let Some(block_trace) = self.get(&block_hash) {
+            return Ok(Some(raw::to_raw(&block_trace)));
+        }
+        let generation = self.generation();
+        let raw = self.inner.get_raw_block_trace_by_hash(block_hash).await?;
+        if let Some(raw) = &raw {
+            self.insert_raw(generation, block_hash, None, raw)?;
+        }
+        Ok(raw)
+    }
+
+    async fn get_raw_block_trace_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        let cached = self
+            .state
+            .lock()
+            .expect("cache lock is not poisoned")
+            .by_number
+            .get(&block_number)
+            .copied();
+        match cached {
+            Some(block_hash) => {
+                if let Some(block_trace) = self.get(&block_hash) {
+                    return Ok(Some(raw::to_raw(&block_trace)));
+                }
+            }
+            None => self.miss(),
+        }
+
+        let generation = self.generation();
+        let Some(block_hash) = self.inner.get_block_hash_by_number(block_number).await? else {
+            return Ok(None);
+        };
+        let raw = self.inner.get_raw_block_trace_by_hash(block_hash).await?;
+        if let Some(raw) = &raw {
+            self.insert_raw(generation, block_hash, Some(block_number), raw)?;
+        }
+        Ok(raw)
+    }
+
+    /// Downloads are streamed from the inner database.
+    async fn get_block_trace_download(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<TraceDownload>, DatabaseError> {
+        self.inner.get_block_trace_download(block_hash).await
+    }
+
+    async fn get_block_hash_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<FixedBytes<32>>, DatabaseError> {
+        self.inner.get_block_hash_by_number(block_number).await
+    }
+
+    async fn get_highest_block_number(&self) -> Result<Option<u64>, DatabaseError> {
+        self.inner.get_highest_block_number().await
+    }
+
+    async fn delete_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<(), DatabaseError> {
+        let result = self.inner.delete_block_trace_by_hash(block_hash).await;
+        self.invalidate(&block_hash, None);
+        result
+    }
+
+    async fn delete_block_traces_before(&self, before: u64) -> Result<u64, DatabaseError> {
+        let result = self.inner.delete_block_traces_before(before).await;
+        self.invalidate_all();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpt_trie::partial_trie::HashedPartialTrie;
+    use trace_decoder::{
+        BlockTraceTriePreImages, SeparateStorageTriesPreImage, SeparateTriePreImage,
+        SeparateTriePreImages,
+    };
+
+    /// An in-memory database holding traces by hash.
+    #[derive(Default)]
+    struct Memory {
+        traces: Mutex<HashMap<FixedBytes<32>, (u64, BlockTrace)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Database for Memory {
+        async fn commit_block_trace(
+            &self,
+            block_hash: FixedBytes<32>,
+            block_number: u64,
+            block_trace: BlockTrace,
+        ) -> Result<(), DatabaseError> {
+            let mut traces = self.traces.lock().unwrap();
+            traces.insert(block_hash, (block_number, block_trace));
+            Ok(())
+        }
+
+        async fn get_block_trace_by_hash(
+            &self,
+            block_hash: FixedBytes<32>,
+        ) -> Result<Option<BlockTrace>, DatabaseError> {
+            let traces = self.traces.lock().unwrap();
+            Ok(traces.get(&block_hash).map(|(_, trace)| trace.clone()))
+        }
+
+        async fn get_block_trace_by_number(
+            &self,
+            block_number: u64,
+        ) -> Result<Option<BlockTrace>, DatabaseError> {
+            match self.get_block_hash_by_number(block_number).await? {
+                Some(block_hash) => self.get_block_trace_by_hash(block_hash).await,
+                None => Ok(None),
+            }
+        }
+
+        async fn get_block_hash_by_number(
+            &self,
+            block_number: u64,
+        ) -> Result<Option<FixedBytes<32>>, DatabaseError> {
+            let traces = self.traces.lock().unwrap();
+            Ok(traces
+                .iter()
+                .find(|(_, (number, _))| *number == block_number)
+                .map(|(hash, _)| *hash))
+        }
+
+        async fn get_highest_block_number(&self) -> Result<Option<u64>, DatabaseError> {
+            let traces = self.traces.lock().unwrap();
+            Ok(traces.values().map(|(number, _)| *number).max())
+        }
+
+        async fn delete_block_trace_by_hash(
+            &self,
+            block_hash: FixedBytes<32>,
+        ) -> Result<(), DatabaseError> {
+            self.traces.lock().unwrap().remove(&block_hash);
+            Ok(())
+        }
+
+        async fn delete_block_traces_before(&self, before: u64) -> Result<u64, DatabaseError> {
+            let mut traces = self.traces.lock().unwrap();
+            let count = traces.len();
+            traces.retain(|_, (number, _)| *number >= before);
+            Ok((count - traces.len()) as u64)
+        }
+    }
+
+    fn block_trace() -> BlockTrace {
+        BlockTrace {
+            trie_pre_images: BlockTraceTriePreImages::Separate(SeparateTriePreImages {
+                state: SeparateTriePreImage::Direct(HashedPartialTrie::default()),
+                storage: SeparateStorageTriesPreImage::MultipleTries(HashMap::new()),
+            }),
+            code_db: Some(HashMap::new()),
+            txn_info: vec![],
+        }
+    }
+
+    /// A cache over an in-memory database holding one trace per block number.
+    async fn cached(blocks: impl IntoIterator<Item = u64>) -> Cached {
+        let inner = Memory::default();
+        for block_number in blocks {
+            inner
+                .commit_block_trace(block_hash(block_number), block_number, block_trace())
+                .await
+                .unwrap();
+        }
+        Cached::new(Arc::new(inner), usize::MAX)
+    }
+
+    fn block_hash(block_number: u64) -> FixedBytes<32> {
+        FixedBytes::from(revm::primitives::U256::from(block_number))
+    }
+
+    fn is_cached(cached: &Cached, block_number: u64) -> bool {
+        let state = cached.state.lock().unwrap();
+        state.entries.contains_key(&block_hash(block_number))
+            || state.by_number.contains_key(&block_number)
+    }
+
+    #[tokio::test]
+    async fn write_during_read_suppresses_insert() {
+        let cached = cached([1]).await;
+
+        // a read takes the generation, then a commit lands before its insert
+        let generation = cached.generation();
+        let stale = cached
+            .inner
+            .get_block_trace_by_hash(block_hash(1))
+            .await
+            .unwrap()
+            .unwrap();
+        cached
+            .commit_block_trace(block_hash(1), 1, block_trace())
+            .await
+            .unwrap();
+        let size = encoded_size(&stale);
+        cached.insert(generation, block_hash(1), Some(1), &stale, size);
+        assert!(!is_cached(&cached, 1));
+
+        // a read starting after the commit is cached
+        cached.get_block_trace_by_number(1).await.unwrap();
+        assert!(is_cached(&cached, 1));
+    }
+
+    #[tokio::test]
+    async fn delete_and_prune_evict_entries() {
+        let cached = cached([1, 2, 3]).await;
+        for block_number in [1, 2, 3] {
+            cached
+                .get_block_trace_by_number(block_number)
+                .await
+                .unwrap();
+            assert!(is_cached(&cached, block_number));
+        }
+
+        cached
+            .delete_block_trace_by_hash(block_hash(3))
+            .await
+            .unwrap();
+        assert!(!is_cached(&cached, 3));
+        assert!(cached.get_block_trace_by_number(3).await.unwrap().is_none());
+
+        assert_eq!(cached.delete_block_traces_before(2).await.unwrap(), 1);
+        assert!(!is_cached(&cached, 1));
+        assert!(cached.get_block_trace_by_number(1).await.unwrap().is_none());
+        assert!(cached.get_block_trace_by_number(2).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn raw_reads_are_cached() {
+        let cached = cached([1]).await;
+
+        let raw = cached.get_raw_block_trace_by_number(1).await.unwrap();
+        assert!(raw.is_some());
+        assert!(is_cached(&cached, 1));
+    }
+}
@@ -0,0 +1,170 @@
+use futures::future::join_all;
+use revm::primitives::FixedBytes;
+use serde_json::value::RawValue;
+use std::sync::Arc;
+use trace_decoder::BlockTrace;
+use tracing::warn;
+
+use super::{Database, DatabaseError, TraceDownload};
+
+/// A database fanning out writes to several sinks.
+///
+/// Traces are written to and deleted from every sink, and read from the first
+/// sink holding them. A sink failing a read is skipped, its error is returned
+/// only if no other sink has the trace.
+pub struct Composite {
+    sinks: Vec<Arc<dyn Database>>,
+}
+
+impl Composite {
+    pub fn new(sinks: Vec<Arc<dyn Database>>) -> Self {
+        Self { sinks }
+    }
+
+    /// Read from the first sink holding the requested value.
+    async fn read<'a, T, F, Fut>(&'a self, read: F) -> Result<Option<T>, DatabaseError>
+    where
+        F: Fn(&'a Arc<dyn Database>) -> Fut,
+        Fut: std::future::Future<Output = Result<Option<T>, DatabaseError>>,
+    {
+        let mut error = None;
+        for sink in &self.sinks {
+            match read(sink).await {
+                Ok(Some(value)) => return Ok(Some(value)),
+                Ok(None) => {}
+                Err(err) => {
+                    warn!("Failed to read from database sink: {err}");
+                    error.get_or_insert(err);
+                }
+            }
+        }
+        error.map_or(Ok(None), Err)
+    }
+}
+
+/// Return the first error of a fanned out write, after all sinks completed.
+fn first_error(results: Vec<Result<(), DatabaseError>>) -> Result<(), DatabaseError> {
+    results.into_iter().collect()
+}
+
+#[async_trait::async_trait]
+impl Database for Composite {
+    async fn commit_block_trace(
+        &self,
+        block_hash: FixedBytes<32>,
+        block_number: u64,
+        block_trace: BlockTrace,
+    ) -> Result<(), DatabaseError> {
+        first_error(
+            join_all(self.sinks.iter().map(|sink| {
+                sink.commit_block_trace(block_hash, block_number, block_trace.clone())
+            }))
+            .await,
+        )
+    }
+
+    async fn get_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<BlockTrace>, DatabaseError> {
+        self.read(|sink| sink.get_block_trace_by_hash(block_hash))
+            .await
+    }
+
+    async fn get_block_trace_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<BlockTrace>, DatabaseError> {
+        self.read(|sink| sink.get_block_trace_by_number(block_number))
+            .await
+    }
+
+    async fn get_raw_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        self.read(|sink| sink.get_raw_block_trace_by_hash(block_hash))
+            .await
+    }
+
+    async fn get_raw_block_trace_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        self.read(|sink| sink.get_raw_block_trace_by_number(block_number))
+            .await
+    }
+
+    async fn get_block_trace_download(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<TraceDownload>, DatabaseError> {
+        self.read(|sink| sink.get_block_trace_download(block_hash))
+            .await
+    }
+
+    async fn get_block_hash_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<FixedBytes<32>>, DatabaseError> {
+        self.read(|sink| sink.get_block_hash_by_number(block_number))
+            .await
+    }
+
+    /// The highest block number held by any sink. A failing sink is skipped,
+    /// its error is returned only if no sink answered.
+    async fn get_highest_block_number(&self) -> Result<Option<u64>, DatabaseError> {
+        let mut highest = None;
+        let mut answered = false;
+        let mut error = None;
+        for result in join_all(
+            self.sinks
+                .iter()
+                .map(|sink| sink.get_highest_block_number()),
+        )
+        .await
+        {
+            match result {
+                Ok(block_number) => {
+                    answered = true;
+                    highest = highest.max(block_number);
+                }
+                Err(err) => {
+                    warn!("Failed to read from database sink: {err}");
+                    error.get_or_insert(err);
+                }
+            }
+        }
+        match (answered, error) {
+            (false, Some(err)) => Err(err),
+            _ => Ok(highest),
+        }
+    }
+
+    async fn delete_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<(), DatabaseError> {
+        first_error(
+            join_all(
+                self.sinks
+                    .iter()
+                    .map(|sink| sink.delete_block_trace_by_hash(block_hash)),
+            )
+            .await,
+        )
+    }
+
+    /// Returns the highest number of traces deleted from a single sink.
+    async fn delete_block_traces_before(&self, before: u64) -> Result<u64, DatabaseError> {
+        let results = join_all(
+            self.sinks
+                .iter()
+                .map(|sink| sink.delete_block_traces_before(before)),
+        )
+        .await;
+        results
+            .into_iter()
+            .try_fold(0, |deleted, result| Ok(deleted.max(result?)))
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use super::TraceLayout;
+
+/// Connection settings of the SQL backends.
+///
+/// Reads and writes use separate pools, so RPC reads do not queue behind the
+/// ExEx committing block traces.
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    /// The storage layout of block traces.
+    pub layout: TraceLayout,
+    /// The maximum number of connections of the read pool.
+    pub read_connections: u32,
+    /// The maximum number of connections of the write pool. SQLite always
+    /// uses a single writer.
+    pub write_connections: u32,
+    /// How long to wait for a pooled connection.
+    pub acquire_timeout: Duration,
+    /// How long an unused connection is kept open, `None` keeps it forever.
+    pub idle_timeout: Option<Duration>,
+    /// The Postgres statement timeout, or the SQLite busy timeout.
+    pub statement_timeout: Option<Duration>,
+    /// The SQLite journal mode.
+    pub journal_mode: JournalMode,
+    /// The SQLite synchronous setting.
+    pub synchronous: Synchronous,
+    /// Whether the filesystem archive compresses traces with zstd.
+    pub compress: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            layout: TraceLayout::default(),
+            read_connections: 4,
+            write_connections: 1,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(600)),
+            statement_timeout: None,
+            journal_mode: JournalMode::default(),
+            synchronous: Synchronous::default(),
+            compress: false,
+        }
+    }
+}
+
+/// The SQLite journal mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    /// Write-ahead logging, which allows reads concurrent with the writer.
+    #[default]
+    Wal,
+    Off,
+}
+
+impl From<JournalMode> for sqlx::sqlite::SqliteJournalMode {
+    fn from(mode: JournalMode) -> Self {
+        match mode {
+            JournalMode::Delete => Self::Delete,
+            JournalMode::Truncate => Self::Truncate,
+            JournalMode::Persist => Self::Persist,
+            JournalMode::Memory => Self::Memory,
+            JournalMode::Wal => Self::Wal,
+            JournalMode::Off => Self::Off,
+        }
+    }
+}
+
+/// The SQLite synchronous setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Synchronous {
+    Off,
+    /// Safe in WAL mode, a power loss may roll back the latest commits.
+    #[default]
+    Normal,
+    Full,
+    Extra,
+}
+
+impl From<Synchronous> for sqlx::sqlite::SqliteSynchronous {
+    fn from(synchronous: Synchronous) -> Self {
+        match synchronous {
+            Synchronous::Off => Self::Off,
+            Synchronous::Normal => Self::Normal,
+            Synchronous::Full => Self::Full,
+            Synchronous::Extra => Self::Extra,
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use revm::primitives::FixedBytes;
+use serde_json::{json, value::RawValue};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex};
+use trace_decoder::BlockTrace;
+
+use super::{Database, DatabaseError, TraceDownload};
+
+/// The append only log of committed and reverted traces.
+const INDEX_FILE: &str = "index.ndjson";
+/// The directory reverted traces are moved to.
+const REVERTED_DIR: &str = "reverted";
+
+/// An archive writing each block trace to `<dir>/<number>-<hash>.json`, or
+/// `.json.zst` when compressed.
+///
+/// Files are written to a temporary file and renamed into place, so readers of
+/// the directory never observe partial traces. Every commit and revert is
+/// appended to `index.ndjson`, and reverted traces are moved to `reverted/`.
+/// Pruned traces are removed and logged as well.
+/// Traces are read according to their own file extension, so archives written
+/// with and without compression can be mixed.
+pub struct Filesystem {
+    dir: PathBuf,
+    compress: bool,
+    index: Mutex<Index>,
+    /// Counter making the temporary file names of concurrent writes unique.
+    tmp_counter: AtomicU64,
+}
+
+/// The traces present in the archive directory.
+#[derive(Debug, Default)]
+struct Index {
+    by_hash: HashMap<FixedBytes<32>, Entry>,
+    by_number: BTreeMap<u64, FixedBytes<32>>,
+}
+
+/// A trace file in the archive directory.
+#[derive(Debug, Clone)]
+struct Entry {
+    block_number: u64,
+    path: PathBuf,
+}
+
+impl Filesystem {
+    pub async fn new(dir: &str, compress: bool) -> Result<Self, DatabaseError> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(dir.join(REVERTED_DIR))
+            .await
+            .map_err(|err| DatabaseError::NotWritable(dir.clone(), err))?;
+        Self::load(dir, compress).await
+    }
+
+    /// Open an existing trace directory without creating it.
+    pub async fn open_existing(dir: &str, compress: bool) -> Result<Self, DatabaseError> {
+        Self::load(PathBuf::from(dir), compress).await
+    }
+
+    /// Rebuild the index from the trace file names.
+    async fn load(dir: PathBuf, compress: bool) -> Result<Self, DatabaseError> {
+        let mut index = Index::default();
+        let mut entries = fs::read_dir(&dir)
+            .await
+            .map_err(|err| DatabaseError::Filesystem(dir.clone(), err))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|err| DatabaseError::Filesystem(dir.clone(), err))?
+        {
+            if let Some((block_number, hash)) =
+                parse_file_name(&entry.file_name().to_string_lossy())
+            {
+                let path = entry.path();
+                index.by_hash.insert(hash, Entry { block_number, path });
+                index.by_number.insert(block_number, hash);
+            }
+        }
+
+        Ok(Self {
+            dir,
+            compress,
+            index: Mutex::new(index),
+            tmp_counter: AtomicU64::new(0),
+        })
+    }
+
+    /// The path new traces are written to, depending on the compression setting.
+    fn path(&self, block_number: u64, block_hash: FixedBytes<32>) -> PathBuf {
+        let extension = if self.compress { "json.zst" } else { "json" };
+        self.dir
+            .join(format!("{block_number}-{block_hash}.{extension}"))
+    }
+
+    /// The stored trace file of a block hash.
+    async fn entry_by_hash(&self, block_hash: FixedBytes<32>) -> Option<Entry> {
+        self.index.lock().await.by_hash.get(&block_hash).cloned()
+    }
+
+    /// The stored trace file of a block number.
+    async fn entry_by_number(&self, block_number: u64) -> Option<Entry> {
+        let index = self.index.lock().await;
+        let block_hash = index.by_number.get(&block_number)?;
+        index.by_hash.get(block_hash).cloned()
+    }
+
+    /// Append a commit or revert event to the index file.
+    async fn append_index(
+        &self,
+        action: &str,
+        block_number: u64,
+        block_hash: FixedBytes<32>,
+        trace_path: &Path,
+    ) -> Result<(), DatabaseError> {
+        let path = self.dir.join(INDEX_FILE);
+        let file_name = trace_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        let line = json!({
+            "action": action,
+            "block_number": block_number,
+            "block_hash": block_hash,
+            "file": file_name,
+        });
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(|err| DatabaseError::Filesystem(path.clone(), err))?;
+        file.write_all(format!("{line}\n").as_bytes())
+            .await
+            .map_err(|err| DatabaseError::Filesystem(path, err))?;
+        Ok(())
+    }
+
+    async fn read(&self, entry: Option<Entry>) -> Result<Option<BlockTrace>, DatabaseError> {
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+        let Some(bytes) = read_bytes(&entry.path).await? else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|err| DatabaseError::MalformedFile(entry.path, err))
+    }
+
+    async fn read_raw(&self, entry: Option<Entry>) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+        let Some(bytes) = read_bytes(&entry.path).await? else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|err| DatabaseError::MalformedFile(entry.path, err))
+    }
+}
+
+/// Read the JSON encoding of a block trace, decompressing `.zst` files.
+async fn read_bytes(path: &Path) -> Result<Option<Vec<u8>>, DatabaseError> {
+    let bytes = match fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(DatabaseError::Filesystem(path.to_path_buf(), err)),
+    };
+    let bytes = match is_compressed(path) {
+        true => zstd::decode_all(bytes.as_slice())
+            .map_err(|err| DatabaseError::Filesystem(path.to_path_buf(), err))?,
+        false => bytes,
+    };
+    Ok(Some(bytes))
+}
+
+/// Whether a trace file is compressed with zstd.
+fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "zst")
+}
+
+/// Parse the block number and hash from a trace file name.
+fn parse_file_name(name: &str) -> Option<(u64, FixedBytes<32>)> {
+    let stem = name
+        .strip_suffix(".json.zst")
+        .or_else(|| name.strip_suffix(".json"))?;
+    let (number, hash) = stem.split_once('-')?;
+    Some((number.parse().ok()?, FixedBytes::from_str(hash).ok()?))
+}
+
+async fn write_atomic(path: &Path, tmp_id: u64, bytes: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{tmp_id}.tmp"));
+    let mut file = fs::File::create(&tmp).await?;
+    file.write_all(bytes).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp, path).await
+}
+
+/// Remove a trace file, ignoring files which are already gone.
+async fn remove_file(path: &Path) -> Result<(), DatabaseError> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(DatabaseError::Filesystem(path.to_path_buf(), err)),
+    }
+}
+
+#[async_trait::async_trait]
+impl Database for Filesystem {
+    async fn commit_block_trace(
+        &self,
+        block_hash: FixedBytes<32>,
+        block_number: u64,
+        block_trace: BlockTrace,
+    ) -> Result<(), DatabaseError> {
+        let path = self.path(block_number, block_hash);
+        let json = serde_json::to_vec(&block_trace).expect("block trace is serializable");
+        let bytes = match self.compress {
+            true => zstd::encode_all(json.as_slice(), 0)
+                .map_err(|err| DatabaseError::Filesystem(path.clone(), err))?,
+            false => json,
+        };
+
+        let tmp_id = self.tmp_counter.fetch_add(1, Ordering::Relaxed);
+        write_atomic(&path, tmp_id, &bytes)
+            .await
+            .map_err(|err| DatabaseError::Filesystem(path.clone(), err))?;
+
+        let mut index = self.index.lock().await;
+        // a trace committed before toggling compression is replaced
+        if let Some(previous) = index.by_hash.get(&block_hash) {
+            if previous.path != path {
+                remove_file(&previous.path).await?;
+            }
+        }
+        self.append_index("commit", block_number, block_hash, &path)
+            .await?;
+        index
+            .by_hash
+            .insert(block_hash, Entry { block_number, path });
+        index.by_number.insert(block_number, block_hash);
+        Ok(())
+    }
+
+    async fn get_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<BlockTrace>, DatabaseError> {
+        self.read(self.entry_by_hash(block_hash).await).await
+    }
+
+    async fn get_block_trace_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<BlockTrace>, DatabaseError> {
+        self.read(self.entry_by_number(block_number).await).await
+    }
+
+    async fn get_raw_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        self.read_raw(self.entry_by_hash(block_hash).await).await
+    }
+
+    async fn get_raw_block_trace_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        self.read_raw(self.entry_by_number(block_number).await)
+            .await
+    }
+
+    async fn get_block_trace_download(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<TraceDownload>, DatabaseError> {
+        let Some(entry) = self.entry_by_hash(block_hash).await else {
+            return Ok(None);
+        };
+        match fs::File::open(&entry.path).await {
+            Ok(file) => Ok(Some(TraceDownload::File {
+                file,
+                compressed: is_compressed(&entry.path),
+            })),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(DatabaseError::Filesystem(entry.path, err)),
+        }
+    }
+
+    async fn get_block_hash_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<FixedBytes<32>>, DatabaseError> {
+        Ok(self
+            .index
+            .lock()
+            .await
+            .by_number
+            .get(&block_number)
+            .copied())
+    }
+
+    async fn get_highest_block_number(&self) -> Result<Option<u64>, DatabaseError> {
+        Ok(self
+            .index
+            .lock()
+            .await
+            .by_number
+            .last_key_value()
+            .map(|(block_number, _)| *block_number))
+    }
+
+    async fn delete_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<(), DatabaseError> {
+        let mut index = self.index.lock().await;
+        let Some(entry) = index.by_hash.get(&block_hash).cloned() else {
+            return Ok(());
+        };
+
+        let reverted = self
+            .dir
+            .join(REVERTED_DIR)
+            .join(entry.path.file_name().expect("trace path has a file name"));
+        match fs::rename(&entry.path, &reverted).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(DatabaseError::Filesystem(entry.path, err)),
+        }
+        self.append_index("revert", entry.block_number, block_hash, &entry.path)
+            .await?;
+
+        index.by_hash.remove(&block_hash);
+        if index.by_number.get(&entry.block_number) == Some(&block_hash) {
+            index.by_number.remove(&entry.block_number);
+        }
+        Ok(())
+    }
+
+    /// Pruned traces are removed rather than moved to `reverted/`.
+    async fn delete_block_traces_before(&self, before: u64) -> Result<u64, DatabaseError> {
+        let mut index = self.index.lock().await;
+        let pruned: Vec<_> = index
+            .by_hash
+            .iter()
+            .filter(|(_, entry)| entry.block_number < before)
+            .map(|(block_hash, entry)| (*block_hash, entry.clone()))
+            .collect();
+
+        for (block_hash, entry) in &pruned {
+            remove_file(&entry.path).await?;
+            self.append_index("prune", entry.block_number, *block_hash, &entry.path)
+                .await?;
+            index.by_hash.remove(block_hash);
+            if index.by_number.get(&entry.block_number) == Some(block_hash) {
+                index.by_number.remove(&entry.block_number);
+            }
+        }
+        Ok(pruned.len() as u64)
+    }
+}
@@ -0,0 +1,86 @@
+//! Storage layouts for block traces.
+//!
+//! With the deduplicated layout the revealed nodes of the separate trie
+//! pre-images are stored once in a content addressed `trie_node` table, and
+//! each block only references the nodes of its witness. The stored trace keeps
+//! the trie roots as hash nodes, which are expanded again on read.
+use compat::Compat;
+use mpt_trie::partial_trie::{HashedPartialTrie, Node, PartialTrie};
+use primitive_types::H256;
+use reth_primitives::{constants::EMPTY_ROOT_HASH, keccak256};
+use std::collections::HashMap;
+use trace_decoder::{
+    BlockTrace, BlockTraceTriePreImages, SeparateStorageTriesPreImage, SeparateTriePreImage,
+    SeparateTriePreImages,
+};
+
+use super::{
+    super::tracer::{trie_from_nodes, trie_nodes},
+    DatabaseError,
+};
+
+/// How block traces are laid out in the database.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraceLayout {
+    /// Store each block trace with its full witness.
+    #[default]
+    Inline,
+    /// Store trie nodes once and reference them from each block trace.
+    Deduplicated,
+}
+
+/// Replace the separate trie pre-images of a block trace by their root hashes,
+/// returning the RLP encoded nodes keyed by hash.
+pub(super) fn strip_trie_nodes(block_trace: &mut BlockTrace) -> HashMap<H256, Vec<u8>> {
+    let mut nodes = HashMap::new();
+    for_each_trie(block_trace, |trie| {
+        for node in trie_nodes(trie) {
+            nodes.insert(keccak256(&node).compat(), node);
+        }
+        *trie = HashedPartialTrie::new(Node::Hash(trie.hash()));
+    });
+    nodes
+}
+
+/// Expand the trie roots of a stored block trace using the referenced nodes.
+pub(super) fn rebuild_tries(
+    block_trace: &mut BlockTrace,
+    nodes: HashMap<H256, Vec<u8>>,
+) -> Result<(), DatabaseError> {
+    if nodes.is_empty() {
+        return Ok(());
+    }
+    let mut result = Ok(());
+    for_each_trie(block_trace, |trie| {
+        let Node::Hash(root) = **trie else {
+            return;
+        };
+        if root == EMPTY_ROOT_HASH.compat() {
+            *trie = HashedPartialTrie::default();
+            return;
+        }
+        match trie_from_nodes(root, &nodes) {
+            Ok(rebuilt) => *trie = rebuilt,
+            Err(err) => result = Err(DatabaseError::MalformedTrieNode(root, err)),
+        }
+    });
+    result
+}
+
+fn for_each_trie(block_trace: &mut BlockTrace, mut f: impl FnMut(&mut HashedPartialTrie)) {
+    let BlockTraceTriePreImages::Separate(SeparateTriePreImages {
+        state: SeparateTriePreImage::Direct(state),
+        storage: SeparateStorageTriesPreImage::MultipleTries(storage),
+    }) = &mut block_trace.trie_pre_images
+    else {
+        return;
+    };
+
+    f(state);
+    for trie in storage.values_mut() {
+        #[allow(irrefutable_let_patterns)]
+        if let SeparateTriePreImage::Direct(trie) = trie {
+            f(trie);
+        }
+    }
+}
@@ -1,14 +1,78 @@
+use compat::Compat;
+use primitive_types::H256;
+use reth_primitives::KECCAK_EMPTY;
 use revm::primitives::FixedBytes;
+use serde_json::value::RawValue;
+use std::{collections::HashMap, sync::Arc};
 use trace_decoder::BlockTrace;
 
 use super::error::DatabaseError;
 
+mod cache;
+mod composite;
+mod config;
+mod fs;
+mod layout;
 mod pg;
+mod raw;
 mod sqlite;
 
+pub use cache::Cached;
+pub use composite::Composite;
+pub use config::{DatabaseConfig, JournalMode, Synchronous};
+pub use fs::Filesystem;
+pub use layout::TraceLayout;
 pub use pg::Postgres;
 pub use sqlite::Sqlite;
 
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DatabaseType {
+    #[default]
+    Sqlite,
+    Postgres,
+    Filesystem,
+}
+
+/// Open a database of the given type at `path`.
+pub async fn open(
+    db_type: DatabaseType,
+    path: &str,
+    config: &DatabaseConfig,
+) -> Result<Arc<dyn Database>, DatabaseError> {
+    Ok(match db_type {
+        DatabaseType::Sqlite => Arc::new(Sqlite::new(path, config).await?),
+        DatabaseType::Postgres => Arc::new(Postgres::new(path, config).await?),
+        DatabaseType::Filesystem => Arc::new(Filesystem::new(path, config.compress).await?),
+    })
+}
+
+/// Open an existing database of the given type at `path` for reading only.
+/// Unlike [`open`], a missing database is an error rather than created.
+pub async fn open_read_only(
+    db_type: DatabaseType,
+    path: &str,
+    config: &DatabaseConfig,
+) -> Result<Arc<dyn Database>, DatabaseError> {
+    Ok(match db_type {
+        DatabaseType::Sqlite => Arc::new(Sqlite::open_read_only(path, config).await?),
+        DatabaseType::Postgres => Arc::new(Postgres::open_read_only(path, config).await?),
+        DatabaseType::Filesystem => {
+            Arc::new(Filesystem::open_existing(path, config.compress).await?)
+        }
+    })
+}
+
+/// The body of a block trace download.
+pub enum TraceDownload {
+    /// The JSON encoding of the trace.
+    Json(Box<RawValue>),
+    /// A trace file streamed from disk, compressed with zstd if `compressed`.
+    File {
+        file: tokio::fs::File,
+        compressed: bool,
+    },
+}
+
 #[async_trait::async_trait]
 pub trait Database: Sync + Send {
     async fn commit_block_trace(
@@ -28,8 +92,102 @@ pub trait Database: Sync + Send {
         block_number: u64,
     ) -> Result<Option<BlockTrace>, DatabaseError>;
 
+    /// Get the JSON encoding of a block trace by block hash. Backends storing
+    /// JSON serve it without decoding the trace.
+    async fn get_raw_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        Ok(self
+            .get_block_trace_by_hash(block_hash)
+            .await?
+            .map(|block_trace| raw::to_raw(&block_trace)))
+    }
+
+    /// Get the JSON encoding of a block trace by block number.
+    async fn get_raw_block_trace_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        Ok(self
+            .get_block_trace_by_number(block_number)
+            .await?
+            .map(|block_trace| raw::to_raw(&block_trace)))
+    }
+
+    /// Open a block trace for download by block hash. Backends storing traces
+    /// as files stream them from disk.
+    async fn get_block_trace_download(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<TraceDownload>, DatabaseError> {
+        Ok(self
+            .get_raw_block_trace_by_hash(block_hash)
+            .await?
+            .map(TraceDownload::Json))
+    }
+
+    async fn get_block_hash_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<FixedBytes<32>>, DatabaseError>;
+
+    /// Get the highest block number holding a trace.
+    async fn get_highest_block_number(&self) -> Result<Option<u64>, DatabaseError>;
+
     async fn delete_block_trace_by_hash(
         &self,
         block_hash: FixedBytes<32>,
     ) -> Result<(), DatabaseError>;
+
+    /// Delete the traces of all blocks below `before`, returning the number of
+    /// deleted traces.
+    async fn delete_block_traces_before(&self, before: u64) -> Result<u64, DatabaseError>;
+
+    /// Check that the database is reachable.
+    async fn ping(&self) -> Result<(), DatabaseError> {
+        self.get_block_hash_by_number(0).await.map(|_| ())
+    }
+}
+
+/// Move the contract code out of a block trace, leaving code hash references
+/// with empty bytecode behind. The code is stored once in the content
+/// addressed `contract_code` table and shared between blocks.
+fn strip_code_db(block_trace: &mut BlockTrace) -> Vec<(H256, Vec<u8>)> {
+    block_trace
+        .code_db
+        .iter_mut()
+        .flatten()
+        .filter(|(_, code)| !code.is_empty())
+        .map(|(hash, code)| (*hash, std::mem::take(code)))
+        .collect()
+}
+
+/// The code hashes referenced by a stored block trace which have to be loaded
+/// from the `contract_code` table.
+fn code_references(block_trace: &BlockTrace) -> Vec<H256> {
+    block_trace
+        .code_db
+        .iter()
+        .flatten()
+        .filter(|(hash, code)| code.is_empty() && **hash != KECCAK_EMPTY.compat())
+        .map(|(hash, _)| *hash)
+        .collect()
+}
+
+/// Match the `contract_code` rows fetched for `hashes` to their code hashes,
+/// failing if any of them is missing.
+fn match_code(
+    hashes: &[H256],
+    rows: impl IntoIterator<Item = (String, Vec<u8>)>,
+) -> Result<HashMap<H256, Vec<u8>>, DatabaseError> {
+    let mut rows: HashMap<String, Vec<u8>> = rows.into_iter().collect();
+    hashes
+        .iter()
+        .map(|hash| {
+            rows.remove(&format!("{hash:?}"))
+                .map(|code| (*hash, code))
+                .ok_or(DatabaseError::MissingCode(*hash))
+        })
+        .collect()
 }
@@ -1,42 +1,261 @@
-use super::{Database, DatabaseError};
+use super::{
+    code_references,
+    layout::{rebuild_tries, strip_trie_nodes},
+    match_code,
+    raw::{self, StoredTrace},
+    strip_code_db, Database, DatabaseConfig, DatabaseError, TraceLayout,
+};
+use primitive_types::H256;
 use revm::primitives::FixedBytes;
+use serde_json::{json, value::RawValue};
 use sqlx::{
-    postgres::{PgPool, PgPoolOptions},
-    Row,
+    postgres::{PgConnectOptions, PgPool, PgPoolOptions},
+    Row, Transaction,
 };
+use std::{collections::HashMap, str::FromStr};
 
 use trace_decoder::BlockTrace;
 
+/// The channel notified with the block number, hash and action whenever a
+/// trace is committed or deleted.
+pub const NOTIFY_CHANNEL: &str = "zeth_block_trace";
+
+/// The transaction level advisory lock held by every write of trie node
+/// references, so a node is never deleted while another block references it.
+const TRIE_NODE_LOCK: i64 = 0x7a65_7468;
+
 #[derive(Debug, Clone)]
 pub struct Postgres {
-    pool: PgPool,
+    reader: PgPool,
+    writer: PgPool,
+    layout: TraceLayout,
 }
 
 impl Postgres {
-    pub async fn new(path: &str) -> Result<Self, DatabaseError> {
-        let pool = PgPoolOptions::new()
-            .max_connections(1)
-            .connect(path)
+    pub async fn new(path: &str, config: &DatabaseConfig) -> Result<Self, DatabaseError> {
+        let mut options =
+            PgConnectOptions::from_str(path).map_err(DatabaseError::OpenConnection)?;
+        if let Some(timeout) = config.statement_timeout {
+            options = options.options([("statement_timeout", timeout.as_millis())]);
+        }
+
+        let pool = |max_connections| {
+            PgPoolOptions::new()
+                .max_connections(max_connections)
+                .acquire_timeout(config.acquire_timeout)
+                .idle_timeout(config.idle_timeout)
+                .connect_with(options.clone())
+        };
+        let writer = pool(config.write_connections)
             .await
             .map_err(DatabaseError::OpenConnection)?;
-        let database = Self { pool };
+        let reader = pool(config.read_connections)
+            .await
+            .map_err(DatabaseError::OpenConnection)?;
+        let database = Self {
+            reader,
+            writer,
+            layout: config.layout,
+        };
         database.create_tables().await?;
         Ok(database)
     }
 
+    /// Open an existing database read-only, without creating or migrating
+    /// its tables.
+    pub async fn open_read_only(
+        path: &str,
+        config: &DatabaseConfig,
+    ) -> Result<Self, DatabaseError> {
+        let mut options = PgConnectOptions::from_str(path)
+            .map_err(DatabaseError::OpenConnection)?
+            .options([("default_transaction_read_only", "on")]);
+        if let Some(timeout) = config.statement_timeout {
+            options = options.options([("statement_timeout", timeout.as_millis())]);
+        }
+
+        let reader = PgPoolOptions::new()
+            .max_connections(config.read_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .idle_timeout(config.idle_timeout)
+            .connect_with(options)
+            .await
+            .map_err(DatabaseError::OpenConnection)?;
+        Ok(Self {
+            writer: reader.clone(),
+            reader,
+            layout: config.layout,
+        })
+    }
+
     async fn create_tables(&self) -> Result<(), DatabaseError> {
         sqlx::query(
             "CREATE TABLE IF NOT EXISTS block_trace (
                 block_hash TEXT PRIMARY KEY,
-                block_number INTEGER NOT NULL,
-                block_trace TEXT NOT NULL
+                block_number BIGINT NOT NULL,
+                block_trace JSONB NOT NULL
+            )",
+        )
+        .execute(&self.writer)
+        .await
+        .map_err(DatabaseError::CreateTables)?;
+        // migrate tables created with TEXT traces and INTEGER block numbers
+        sqlx::query(
+            "DO $$ BEGIN
+                IF EXISTS (
+                    SELECT 1 FROM information_schema.columns
+                    WHERE table_name = 'block_trace'
+                        AND column_name = 'block_trace'
+                        AND data_type = 'text'
+                ) THEN
+                    ALTER TABLE block_trace
+                        ALTER COLUMN block_number TYPE BIGINT,
+                        ALTER COLUMN block_trace TYPE JSONB USING block_trace::jsonb;
+                END IF;
+            END $$",
+        )
+        .execute(&self.writer)
+        .await
+        .map_err(DatabaseError::CreateTables)?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS block_trace_block_number ON block_trace (block_number)",
+        )
+        .execute(&self.writer)
+        .await
+        .map_err(DatabaseError::CreateTables)?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS contract_code (
+                code_hash TEXT PRIMARY KEY,
+                code BYTEA NOT NULL
             )",
         )
-        .execute(&self.pool)
+        .execute(&self.writer)
+        .await
+        .map_err(DatabaseError::CreateTables)?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS trie_node (
+                node_hash TEXT PRIMARY KEY,
+                node BYTEA NOT NULL
+            )",
+        )
+        .execute(&self.writer)
+        .await
+        .map_err(DatabaseError::CreateTables)?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS block_trie_node (
+                block_hash TEXT NOT NULL,
+                node_hash TEXT NOT NULL,
+                PRIMARY KEY (block_hash, node_hash)
+            )",
+        )
+        .execute(&self.writer)
+        .await
+        .map_err(DatabaseError::CreateTables)?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS block_trie_node_node_hash ON block_trie_node (node_hash)",
+        )
+        .execute(&self.writer)
         .await
         .map_err(DatabaseError::CreateTables)?;
         Ok(())
     }
+
+    /// Expand the trie pre-images of a block trace stored with the
+    /// deduplicated layout.
+    async fn load_trie_nodes(
+        &self,
+        block_hash: &str,
+        block_trace: &mut BlockTrace,
+    ) -> Result<(), DatabaseError> {
+        // a referenced node without a stored node would leave the witness
+        // blinded, so it is an error rather than skipped
+        let nodes = sqlx::query(
+            "SELECT block_trie_node.node_hash, trie_node.node FROM block_trie_node
+            LEFT JOIN trie_node ON trie_node.node_hash = block_trie_node.node_hash
+            WHERE block_trie_node.block_hash = $1",
+        )
+        .bind(block_hash)
+        .fetch_all(&self.reader)
+        .await
+        .map_err(DatabaseError::GetTrace)?
+        .into_iter()
+        .map(|row| {
+            let node_hash: String = row.try_get("node_hash").expect("column is well formed");
+            let node_hash = H256::from_str(&node_hash).expect("node hash is well formed");
+            let node: Option<Vec<u8>> = row.try_get("node").expect("column is well formed");
+            node.map(|node| (node_hash, node))
+                .ok_or(DatabaseError::MissingTrieNode(node_hash))
+        })
+        .collect::<Result<_, _>>()?;
+        rebuild_tries(block_trace, nodes)
+    }
+
+    /// Fetch the contract code of the given code hashes.
+    async fn fetch_code(&self, hashes: &[H256]) -> Result<HashMap<H256, Vec<u8>>, DatabaseError> {
+        if hashes.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let rows =
+            sqlx::query("SELECT code_hash, code FROM contract_code WHERE code_hash = ANY($1)")
+                .bind(
+                    hashes
+                        .iter()
+                        .map(|hash| format!("{hash:?}"))
+                        .collect::<Vec<_>>(),
+                )
+                .fetch_all(&self.reader)
+                .await
+                .map_err(DatabaseError::GetTrace)?;
+        match_code(
+            hashes,
+            rows.into_iter().map(|row| {
+                (
+                    row.try_get("code_hash").expect("column is well formed"),
+                    row.try_get("code").expect("column is well formed"),
+                )
+            }),
+        )
+    }
+
+    /// Serve a stored block trace as raw JSON. Only traces stored with the
+    /// deduplicated layout are decoded, to expand their tries, all others are
+    /// served as stored with their contract code spliced in.
+    async fn load_raw(
+        &self,
+        block_hash: &str,
+        block_trace: &str,
+    ) -> Result<Box<RawValue>, DatabaseError> {
+        let has_trie_nodes =
+            sqlx::query("SELECT 1 FROM block_trie_node WHERE block_hash = $1 LIMIT 1")
+                .bind(block_hash)
+                .fetch_optional(&self.reader)
+                .await
+                .map_err(DatabaseError::GetTrace)?
+                .is_some();
+        if has_trie_nodes {
+            let mut block_trace: BlockTrace =
+                serde_json::from_str(block_trace).map_err(DatabaseError::MalformedTrace)?;
+            self.load_trie_nodes(block_hash, &mut block_trace).await?;
+            self.load_code(&mut block_trace).await?;
+            return Ok(raw::to_raw(&block_trace));
+        }
+
+        let stored = StoredTrace::parse(block_trace).map_err(DatabaseError::MalformedTrace)?;
+        let code = self.fetch_code(&stored.code_references()).await?;
+        stored
+            .into_raw(&code)
+            .map_err(DatabaseError::MalformedTrace)
+    }
+
+    /// Load the contract code referenced by a stored block trace.
+    async fn load_code(&self, block_trace: &mut BlockTrace) -> Result<(), DatabaseError> {
+        let code = self.fetch_code(&code_references(block_trace)).await?;
+        block_trace
+            .code_db
+            .get_or_insert_with(Default::default)
+            .extend(code);
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -45,17 +264,51 @@ impl Database for Postgres {
         &self,
         block_hash: FixedBytes<32>,
         block_number: u64,
-        block_trace: BlockTrace,
+        mut block_trace: BlockTrace,
     ) -> Result<(), DatabaseError> {
+        let code = strip_code_db(&mut block_trace);
+        let nodes = match self.layout {
+            TraceLayout::Inline => Default::default(),
+            TraceLayout::Deduplicated => strip_trie_nodes(&mut block_trace),
+        };
+        let mut tx = self
+            .writer
+            .begin()
+            .await
+            .map_err(DatabaseError::InsertTrace)?;
+        lock_trie_nodes(&mut tx)
+            .await
+            .map_err(DatabaseError::InsertTrace)?;
+        for (hash, code) in code {
+            sqlx::query("INSERT INTO contract_code (code_hash, code) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+                .bind(format!("{hash:?}"))
+                .bind(code)
+                .execute(&mut *tx)
+                .await
+                .map_err(DatabaseError::InsertTrace)?;
+        }
+        // a recommitted block replaces its previous trie node references
+        release_trie_nodes(&mut tx, &block_hash.to_string())
+            .await
+            .map_err(DatabaseError::InsertTrace)?;
+        insert_trie_nodes(&mut tx, &block_hash.to_string(), nodes)
+            .await
+            .map_err(DatabaseError::InsertTrace)?;
         sqlx::query(
-            "INSERT INTO block_trace (block_hash, block_number, block_trace) VALUES ($1, $2, $3)",
+            "INSERT INTO block_trace (block_hash, block_number, block_trace) VALUES ($1, $2, $3::jsonb)
+            ON CONFLICT (block_hash) DO UPDATE
+            SET block_number = excluded.block_number, block_trace = excluded.block_trace",
         )
         .bind(block_hash.to_string())
         .bind(block_number as i64)
         .bind(serde_json::to_string(&block_trace).expect("block trace is serializable"))
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
         .map_err(DatabaseError::InsertTrace)?;
+        notify(&mut tx, "commit", block_number as i64, block_hash)
+            .await
+            .map_err(DatabaseError::InsertTrace)?;
+        tx.commit().await.map_err(DatabaseError::InsertTrace)?;
         Ok(())
     }
 
@@ -64,17 +317,20 @@ impl Database for Postgres {
         &self,
         block_hash: FixedBytes<32>,
     ) -> Result<Option<BlockTrace>, DatabaseError> {
-        let row = sqlx::query("SELECT block_trace FROM block_trace WHERE block_hash = $1")
+        let row = sqlx::query("SELECT block_trace::text FROM block_trace WHERE block_hash = $1")
             .bind(block_hash.to_string())
-            .fetch_optional(&self.pool)
+            .fetch_optional(&self.reader)
             .await
             .map_err(DatabaseError::GetTrace)?;
 
         if let Some(row) = row {
             let block_trace: String = row.try_get("block_trace").expect("column is well formed");
-            Ok(Some(
-                serde_json::from_str(&block_trace).expect("block trace is deserializable"),
-            ))
+            let mut block_trace: BlockTrace =
+                serde_json::from_str(&block_trace).map_err(DatabaseError::MalformedTrace)?;
+            self.load_trie_nodes(&block_hash.to_string(), &mut block_trace)
+                .await?;
+            self.load_code(&mut block_trace).await?;
+            Ok(Some(block_trace))
         } else {
             Ok(None)
         }
@@ -85,31 +341,275 @@ impl Database for Postgres {
         &self,
         block_number: u64,
     ) -> Result<Option<BlockTrace>, DatabaseError> {
-        let row = sqlx::query("SELECT block_trace FROM block_trace WHERE block_number = $1")
-            .bind(block_number as i64)
-            .fetch_optional(&self.pool)
+        let row = sqlx::query(
+            "SELECT block_hash, block_trace::text FROM block_trace WHERE block_number = $1",
+        )
+        .bind(block_number as i64)
+        .fetch_optional(&self.reader)
+        .await
+        .map_err(DatabaseError::GetTrace)?;
+
+        if let Some(row) = row {
+            let block_trace: String = row.try_get("block_trace").expect("column is well formed");
+            let mut block_trace: BlockTrace =
+                serde_json::from_str(&block_trace).map_err(DatabaseError::MalformedTrace)?;
+            let block_hash: String = row.try_get("block_hash").expect("column is well formed");
+            self.load_trie_nodes(&block_hash, &mut block_trace).await?;
+            self.load_code(&mut block_trace).await?;
+            Ok(Some(block_trace))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn get_raw_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        let row = sqlx::query("SELECT block_trace::text FROM block_trace WHERE block_hash = $1")
+            .bind(block_hash.to_string())
+            .fetch_optional(&self.reader)
             .await
             .map_err(DatabaseError::GetTrace)?;
 
         if let Some(row) = row {
             let block_trace: String = row.try_get("block_trace").expect("column is well formed");
             Ok(Some(
-                serde_json::from_str(&block_trace).expect("block trace is deserializable"),
+                self.load_raw(&block_hash.to_string(), &block_trace).await?,
             ))
         } else {
             Ok(None)
         }
     }
 
+    async fn get_raw_block_trace_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        let row = sqlx::query(
+            "SELECT block_hash, block_trace::text FROM block_trace WHERE block_number = $1",
+        )
+        .bind(block_number as i64)
+        .fetch_optional(&self.reader)
+        .await
+        .map_err(DatabaseError::GetTrace)?;
+
+        if let Some(row) = row {
+            let block_hash: String = row.try_get("block_hash").expect("column is well formed");
+            let block_trace: String = row.try_get("block_trace").expect("column is well formed");
+            Ok(Some(self.load_raw(&block_hash, &block_trace).await?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn get_block_hash_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<FixedBytes<32>>, DatabaseError> {
+        let row = sqlx::query("SELECT block_hash FROM block_trace WHERE block_number = $1")
+            .bind(block_number as i64)
+            .fetch_optional(&self.reader)
+            .await
+            .map_err(DatabaseError::GetTrace)?;
+
+        Ok(row.map(|row| {
+            let block_hash: String = row.try_get("block_hash").expect("column is well formed");
+            block_hash.parse().expect("block hash is well formed")
+        }))
+    }
+
+    async fn get_highest_block_number(&self) -> Result<Option<u64>, DatabaseError> {
+        let row = sqlx::query("SELECT MAX(block_number) AS block_number FROM block_trace")
+            .fetch_one(&self.reader)
+            .await
+            .map_err(DatabaseError::GetTrace)?;
+
+        let block_number: Option<i64> = row.try_get("block_number").expect("column is well formed");
+        Ok(block_number.map(|block_number| block_number as u64))
+    }
+
     async fn delete_block_trace_by_hash(
         &self,
         block_hash: FixedBytes<32>,
     ) -> Result<(), DatabaseError> {
-        sqlx::query("DELETE FROM block_trace WHERE block_hash = $1")
-            .bind(block_hash.to_string())
-            .execute(&self.pool)
+        let mut tx = self
+            .writer
+            .begin()
             .await
             .map_err(DatabaseError::DeleteTrace)?;
+        lock_trie_nodes(&mut tx)
+            .await
+            .map_err(DatabaseError::DeleteTrace)?;
+        release_trie_nodes(&mut tx, &block_hash.to_string())
+            .await
+            .map_err(DatabaseError::DeleteTrace)?;
+        let deleted =
+            sqlx::query("DELETE FROM block_trace WHERE block_hash = $1 RETURNING block_number")
+                .bind(block_hash.to_string())
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(DatabaseError::DeleteTrace)?;
+        if let Some(row) = deleted {
+            let block_number: i64 = row.try_get("block_number").expect("column is well formed");
+            notify(&mut tx, "delete", block_number, block_hash)
+                .await
+                .map_err(DatabaseError::DeleteTrace)?;
+        }
+        tx.commit().await.map_err(DatabaseError::DeleteTrace)?;
         Ok(())
     }
+
+    async fn delete_block_traces_before(&self, before: u64) -> Result<u64, DatabaseError> {
+        let mut tx = self
+            .writer
+            .begin()
+            .await
+            .map_err(DatabaseError::DeleteTrace)?;
+        lock_trie_nodes(&mut tx)
+            .await
+            .map_err(DatabaseError::DeleteTrace)?;
+        release_trie_nodes_before(&mut tx, before as i64)
+            .await
+            .map_err(DatabaseError::DeleteTrace)?;
+        // every deleted trace is announced, like a single delete
+        let row = sqlx::query(
+            "WITH deleted AS (
+                DELETE FROM block_trace WHERE block_number < $1
+                RETURNING block_number, block_hash
+            )
+            SELECT COUNT(pg_notify($2, json_build_object(
+                'action', 'delete',
+                'block_number', block_number,
+                'block_hash', block_hash
+            )::text)) AS deleted FROM deleted",
+        )
+        .bind(before as i64)
+        .bind(NOTIFY_CHANNEL)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(DatabaseError::DeleteTrace)?;
+        tx.commit().await.map_err(DatabaseError::DeleteTrace)?;
+        let deleted: i64 = row.try_get("deleted").expect("column is well formed");
+        Ok(deleted as u64)
+    }
+}
+
+/// Serialize the writers of trie node references until the transaction ends.
+/// Without it a node released by one block could be deleted after another
+/// block's insert skipped it as already stored.
+async fn lock_trie_nodes(tx: &mut Transaction<'_, sqlx::Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(TRIE_NODE_LOCK)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Store the trie nodes of a block once and reference them from the block.
+async fn insert_trie_nodes(
+    tx: &mut Transaction<'_, sqlx::Postgres>,
+    block_hash: &str,
+    nodes: HashMap<H256, Vec<u8>>,
+) -> Result<(), sqlx::Error> {
+    let (hashes, nodes): (Vec<_>, Vec<_>) = nodes
+        .into_iter()
+        .map(|(hash, node)| (format!("{hash:?}"), node))
+        .unzip();
+    sqlx::query(
+        "INSERT INTO trie_node (node_hash, node)
+        SELECT * FROM UNNEST($1::text[], $2::bytea[])
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(&hashes)
+    .bind(nodes)
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query(
+        "INSERT INTO block_trie_node (block_hash, node_hash)
+        SELECT $1, node_hash FROM UNNEST($2::text[]) AS node_hash
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(block_hash)
+    .bind(&hashes)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Drop the trie node references of a block, deleting the nodes which are no
+/// longer referenced by any other block.
+async fn release_trie_nodes(
+    tx: &mut Transaction<'_, sqlx::Postgres>,
+    block_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM trie_node WHERE node_hash IN (
+            SELECT node_hash FROM block_trie_node WHERE block_hash = $1
+        ) AND NOT EXISTS (
+            SELECT 1 FROM block_trie_node AS other
+            WHERE other.node_hash = trie_node.node_hash AND other.block_hash != $1
+        )",
+    )
+    .bind(block_hash)
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query("DELETE FROM block_trie_node WHERE block_hash = $1")
+        .bind(block_hash)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Drop the trie node references of all blocks below `before`, deleting the
+/// nodes which are no longer referenced by any later block.
+async fn release_trie_nodes_before(
+    tx: &mut Transaction<'_, sqlx::Postgres>,
+    before: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM trie_node WHERE node_hash IN (
+            SELECT node_hash FROM block_trie_node WHERE block_hash IN (
+                SELECT block_hash FROM block_trace WHERE block_number < $1
+            )
+        ) AND NOT EXISTS (
+            SELECT 1 FROM block_trie_node AS other
+            WHERE other.node_hash = trie_node.node_hash AND other.block_hash NOT IN (
+                SELECT block_hash FROM block_trace WHERE block_number < $1
+            )
+        )",
+    )
+    .bind(before)
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query(
+        "DELETE FROM block_trie_node WHERE block_hash IN (
+            SELECT block_hash FROM block_trace WHERE block_number < $1
+        )",
+    )
+    .bind(before)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Notify listeners on the [`NOTIFY_CHANNEL`] of a committed or deleted trace.
+/// The notification is delivered when the transaction commits.
+async fn notify(
+    tx: &mut Transaction<'_, sqlx::Postgres>,
+    action: &str,
+    block_number: i64,
+    block_hash: FixedBytes<32>,
+) -> Result<(), sqlx::Error> {
+    let payload = json!({
+        "action": action,
+        "block_number": block_number,
+        "block_hash": block_hash,
+    });
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(NOTIFY_CHANNEL)
+        .bind(payload.to_string())
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
 }
@@ -0,0 +1,108 @@
+use compat::Compat;
+use primitive_types::H256;
+use reth_primitives::{Bytes, KECCAK_EMPTY};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::collections::HashMap;
+use trace_decoder::BlockTrace;
+
+/// The contract code of a stored block trace, borrowing the encoding of each
+/// entry from the stored JSON.
+#[derive(Deserialize)]
+struct StoredCodeDb<'a> {
+    #[serde(default, borrow)]
+    code_db: Option<HashMap<H256, &'a RawValue>>,
+}
+
+/// A stored block trace served as its stored JSON, with only the contract code
+/// moved to the `contract_code` table spliced back in.
+pub(super) struct StoredTrace<'a> {
+    json: &'a str,
+    references: Vec<(H256, &'a RawValue)>,
+}
+
+impl<'a> StoredTrace<'a> {
+    /// Scan a stored block trace for code references, without decoding its
+    /// tries and transactions.
+    pub(super) fn parse(json: &'a str) -> Result<Self, serde_json::Error> {
+        let stored: StoredCodeDb = serde_json::from_str(json)?;
+        let references = stored
+            .code_db
+            .into_iter()
+            .flatten()
+            .filter(|(hash, code)| is_empty_code(code) && *hash != KECCAK_EMPTY.compat())
+            .collect();
+        Ok(Self { json, references })
+    }
+
+    /// The code hashes whose bytecode has been moved to the `contract_code`
+    /// table.
+    pub(super) fn code_references(&self) -> Vec<H256> {
+        self.references.iter().map(|(hash, _)| *hash).collect()
+    }
+
+    /// The stored JSON with each code reference replaced by its bytecode,
+    /// encoded like the reference.
+    pub(super) fn into_raw(
+        mut self,
+        code: &HashMap<H256, Vec<u8>>,
+    ) -> Result<Box<RawValue>, serde_json::Error> {
+        // the references borrow from the stored JSON, so their offsets locate
+        // them in it
+        self.references
+            .sort_by_key(|(_, value)| value.get().as_ptr() as usize);
+        let base = self.json.as_ptr() as usize;
+        let mut json = String::with_capacity(self.json.len());
+        let mut end = 0;
+        for (hash, value) in &self.references {
+            let Some(code) = code.get(hash) else {
+                continue;
+            };
+            let start = value.get().as_ptr() as usize - base;
+            json.push_str(&self.json[end..start]);
+            match value.get().starts_with('[') {
+                true => json.push_str(&serde_json::to_string(code)?),
+                false => json.push_str(&serde_json::to_string(
+                    &Bytes::from(code.clone()).to_string(),
+                )?),
+            }
+            end = start + value.get().len();
+        }
+        json.push_str(&self.json[end..]);
+        RawValue::from_string(json)
+    }
+}
+
+/// Encode a decoded block trace as raw JSON.
+pub(super) fn to_raw(block_trace: &BlockTrace) -> Box<RawValue> {
+    serde_json::value::to_raw_value(block_trace).expect("block trace is serializable")
+}
+
+fn is_empty_code(code: &RawValue) -> bool {
+    matches!(code.get(), "\"\"" | "\"0x\"" | "[]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_is_spliced_into_the_stored_json() {
+        let hash = H256::repeat_byte(0x11);
+        let json = format!(
+            r#"{{"trie_pre_images":{{"combined":{{"compact":"0x00"}}}},"code_db":{{"{hash:?}":"0x"}},"txn_info":[]}}"#
+        );
+
+        let stored = StoredTrace::parse(&json).unwrap();
+        assert_eq!(stored.code_references(), vec![hash]);
+
+        let raw = stored
+            .into_raw(&HashMap::from([(hash, vec![0x60, 0x00])]))
+            .unwrap();
+        assert_eq!(
+            raw.get(),
+            json.replace(r#""0x"}"#, r#""0x6000"}"#),
+            "only the code reference is replaced"
+        );
+    }
+}
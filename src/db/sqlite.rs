@@ -1,27 +1,101 @@
-use super::{Database, DatabaseError};
+use super::{
+    code_references,
+    layout::{rebuild_tries, strip_trie_nodes},
+    match_code,
+    raw::{self, StoredTrace},
+    strip_code_db, Database, DatabaseConfig, DatabaseError, TraceLayout,
+};
+use primitive_types::H256;
 use revm::primitives::FixedBytes;
+use serde_json::value::RawValue;
 use sqlx::{
-    sqlite::{SqlitePool, SqlitePoolOptions},
-    Row,
+    sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions},
+    QueryBuilder, Row, Transaction,
+};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    path::Path,
+    str::FromStr,
 };
 
 use trace_decoder::BlockTrace;
 
+/// The maximum number of values bound to a single statement, well below the
+/// SQLite default limit of 32766 host parameters.
+const MAX_BIND_PARAMS: usize = 500;
+
 #[derive(Debug, Clone)]
 pub struct Sqlite {
-    pool: SqlitePool,
+    reader: SqlitePool,
+    writer: SqlitePool,
+    layout: TraceLayout,
 }
 
 impl Sqlite {
-    pub async fn new(path: &str) -> Result<Self, DatabaseError> {
-        let pool = SqlitePoolOptions::new()
+    pub async fn new(path: &str, config: &DatabaseConfig) -> Result<Self, DatabaseError> {
+        let mut options = SqliteConnectOptions::from_str(path)
+            .map_err(DatabaseError::OpenConnection)?
+            .create_if_missing(true)
+            .journal_mode(config.journal_mode.into())
+            .synchronous(config.synchronous.into());
+        if let Some(timeout) = config.statement_timeout {
+            options = options.busy_timeout(timeout);
+        }
+
+        ensure_writable(options.get_filename())?;
+
+        // sqlite allows a single writer only
+        let writer = SqlitePoolOptions::new()
             .max_connections(1)
-            .connect(path)
+            .acquire_timeout(config.acquire_timeout)
+            .idle_timeout(config.idle_timeout)
+            .connect_with(options.clone())
             .await
             .map_err(DatabaseError::OpenConnection)?;
-        let database = Self { pool };
+        let database = Self {
+            reader: writer.clone(),
+            writer,
+            layout: config.layout,
+        };
         database.create_tables().await?;
-        Ok(database)
+
+        // the read pool is opened once the tables exist
+        let reader = SqlitePoolOptions::new()
+            .max_connections(config.read_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .idle_timeout(config.idle_timeout)
+            .connect_with(options.read_only(true))
+            .await
+            .map_err(DatabaseError::OpenConnection)?;
+        Ok(Self { reader, ..database })
+    }
+
+    /// Open an existing database read-only, without creating or migrating it.
+    pub async fn open_read_only(
+        path: &str,
+        config: &DatabaseConfig,
+    ) -> Result<Self, DatabaseError> {
+        let mut options = SqliteConnectOptions::from_str(path)
+            .map_err(DatabaseError::OpenConnection)?
+            .create_if_missing(false)
+            .read_only(true);
+        if let Some(timeout) = config.statement_timeout {
+            options = options.busy_timeout(timeout);
+        }
+
+        let reader = SqlitePoolOptions::new()
+            .max_connections(config.read_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .idle_timeout(config.idle_timeout)
+            .connect_with(options)
+            .await
+            .map_err(DatabaseError::OpenConnection)?;
+        Ok(Self {
+            writer: reader.clone(),
+            reader,
+            layout: config.layout,
+        })
     }
 
     async fn create_tables(&self) -> Result<(), DatabaseError> {
@@ -32,11 +106,146 @@ impl Sqlite {
                 block_trace TEXT NOT NULL
             )",
         )
-        .execute(&self.pool)
+        .execute(&self.writer)
+        .await
+        .map_err(DatabaseError::CreateTables)?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS contract_code (
+                code_hash TEXT PRIMARY KEY,
+                code BLOB NOT NULL
+            )",
+        )
+        .execute(&self.writer)
+        .await
+        .map_err(DatabaseError::CreateTables)?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS trie_node (
+                node_hash TEXT PRIMARY KEY,
+                node BLOB NOT NULL
+            )",
+        )
+        .execute(&self.writer)
+        .await
+        .map_err(DatabaseError::CreateTables)?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS block_trie_node (
+                block_hash TEXT NOT NULL,
+                node_hash TEXT NOT NULL,
+                PRIMARY KEY (block_hash, node_hash)
+            )",
+        )
+        .execute(&self.writer)
+        .await
+        .map_err(DatabaseError::CreateTables)?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS block_trie_node_node_hash ON block_trie_node (node_hash)",
+        )
+        .execute(&self.writer)
         .await
         .map_err(DatabaseError::CreateTables)?;
         Ok(())
     }
+
+    /// Expand the trie pre-images of a block trace stored with the
+    /// deduplicated layout.
+    async fn load_trie_nodes(
+        &self,
+        block_hash: &str,
+        block_trace: &mut BlockTrace,
+    ) -> Result<(), DatabaseError> {
+        // a referenced node without a stored node would leave the witness
+        // blinded, so it is an error rather than skipped
+        let nodes = sqlx::query(
+            "SELECT block_trie_node.node_hash, trie_node.node FROM block_trie_node
+            LEFT JOIN trie_node ON trie_node.node_hash = block_trie_node.node_hash
+            WHERE block_trie_node.block_hash = ?",
+        )
+        .bind(block_hash)
+        .fetch_all(&self.reader)
+        .await
+        .map_err(DatabaseError::GetTrace)?
+        .into_iter()
+        .map(|row| {
+            let node_hash: String = row.try_get("node_hash").expect("column is well formed");
+            let node_hash = H256::from_str(&node_hash).expect("node hash is well formed");
+            let node: Option<Vec<u8>> = row.try_get("node").expect("column is well formed");
+            node.map(|node| (node_hash, node))
+                .ok_or(DatabaseError::MissingTrieNode(node_hash))
+        })
+        .collect::<Result<_, _>>()?;
+        rebuild_tries(block_trace, nodes)
+    }
+
+    /// Fetch the contract code of the given code hashes, in chunks of at most
+    /// [`MAX_BIND_PARAMS`] hashes per query.
+    async fn fetch_code(&self, hashes: &[H256]) -> Result<HashMap<H256, Vec<u8>>, DatabaseError> {
+        let mut rows = vec![];
+        for chunk in hashes.chunks(MAX_BIND_PARAMS) {
+            let mut query =
+                QueryBuilder::new("SELECT code_hash, code FROM contract_code WHERE code_hash IN (");
+            let mut bindings = query.separated(", ");
+            for hash in chunk {
+                bindings.push_bind(format!("{hash:?}"));
+            }
+            bindings.push_unseparated(")");
+            rows.extend(
+                query
+                    .build()
+                    .fetch_all(&self.reader)
+                    .await
+                    .map_err(DatabaseError::GetTrace)?,
+            );
+        }
+        match_code(
+            hashes,
+            rows.into_iter().map(|row| {
+                (
+                    row.try_get("code_hash").expect("column is well formed"),
+                    row.try_get("code").expect("column is well formed"),
+                )
+            }),
+        )
+    }
+
+    /// Serve a stored block trace as raw JSON. Only traces stored with the
+    /// deduplicated layout are decoded, to expand their tries, all others are
+    /// served as stored with their contract code spliced in.
+    async fn load_raw(
+        &self,
+        block_hash: &str,
+        block_trace: &str,
+    ) -> Result<Box<RawValue>, DatabaseError> {
+        let has_trie_nodes =
+            sqlx::query("SELECT 1 FROM block_trie_node WHERE block_hash = ? LIMIT 1")
+                .bind(block_hash)
+                .fetch_optional(&self.reader)
+                .await
+                .map_err(DatabaseError::GetTrace)?
+                .is_some();
+        if has_trie_nodes {
+            let mut block_trace: BlockTrace =
+                serde_json::from_str(block_trace).map_err(DatabaseError::MalformedTrace)?;
+            self.load_trie_nodes(block_hash, &mut block_trace).await?;
+            self.load_code(&mut block_trace).await?;
+            return Ok(raw::to_raw(&block_trace));
+        }
+
+        let stored = StoredTrace::parse(block_trace).map_err(DatabaseError::MalformedTrace)?;
+        let code = self.fetch_code(&stored.code_references()).await?;
+        stored
+            .into_raw(&code)
+            .map_err(DatabaseError::MalformedTrace)
+    }
+
+    /// Load the contract code referenced by a stored block trace.
+    async fn load_code(&self, block_trace: &mut BlockTrace) -> Result<(), DatabaseError> {
+        let code = self.fetch_code(&code_references(block_trace)).await?;
+        block_trace
+            .code_db
+            .get_or_insert_with(Default::default)
+            .extend(code);
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -45,17 +254,45 @@ impl Database for Sqlite {
         &self,
         block_hash: FixedBytes<32>,
         block_number: u64,
-        block_trace: BlockTrace,
+        mut block_trace: BlockTrace,
     ) -> Result<(), DatabaseError> {
+        let code = strip_code_db(&mut block_trace);
+        let nodes = match self.layout {
+            TraceLayout::Inline => Default::default(),
+            TraceLayout::Deduplicated => strip_trie_nodes(&mut block_trace),
+        };
+        let mut tx = self
+            .writer
+            .begin()
+            .await
+            .map_err(DatabaseError::InsertTrace)?;
+        for (hash, code) in code {
+            sqlx::query("INSERT OR IGNORE INTO contract_code (code_hash, code) VALUES (?, ?)")
+                .bind(format!("{hash:?}"))
+                .bind(code)
+                .execute(&mut *tx)
+                .await
+                .map_err(DatabaseError::InsertTrace)?;
+        }
+        // a recommitted block replaces its previous trie node references
+        release_trie_nodes(&mut tx, &block_hash.to_string())
+            .await
+            .map_err(DatabaseError::InsertTrace)?;
+        insert_trie_nodes(&mut tx, &block_hash.to_string(), nodes)
+            .await
+            .map_err(DatabaseError::InsertTrace)?;
         sqlx::query(
-            "INSERT INTO block_trace (block_hash, block_number, block_trace) VALUES (?, ?, ?)",
+            "INSERT INTO block_trace (block_hash, block_number, block_trace) VALUES (?, ?, ?)
+            ON CONFLICT (block_hash) DO UPDATE
+            SET block_number = excluded.block_number, block_trace = excluded.block_trace",
         )
         .bind(block_hash.to_string())
         .bind(block_number as i64)
         .bind(serde_json::to_string(&block_trace).expect("block trace is serializable"))
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
         .map_err(DatabaseError::InsertTrace)?;
+        tx.commit().await.map_err(DatabaseError::InsertTrace)?;
         Ok(())
     }
 
@@ -66,15 +303,18 @@ impl Database for Sqlite {
     ) -> Result<Option<BlockTrace>, DatabaseError> {
         let row = sqlx::query("SELECT block_trace FROM block_trace WHERE block_hash = ?")
             .bind(block_hash.to_string())
-            .fetch_optional(&self.pool)
+            .fetch_optional(&self.reader)
             .await
             .map_err(DatabaseError::GetTrace)?;
 
         if let Some(row) = row {
             let block_trace: String = row.try_get("block_trace").expect("column is well formed");
-            Ok(Some(
-                serde_json::from_str(&block_trace).expect("block trace is deserializable"),
-            ))
+            let mut block_trace: BlockTrace =
+                serde_json::from_str(&block_trace).map_err(DatabaseError::MalformedTrace)?;
+            self.load_trie_nodes(&block_hash.to_string(), &mut block_trace)
+                .await?;
+            self.load_code(&mut block_trace).await?;
+            Ok(Some(block_trace))
         } else {
             Ok(None)
         }
@@ -85,31 +325,235 @@ impl Database for Sqlite {
         &self,
         block_number: u64,
     ) -> Result<Option<BlockTrace>, DatabaseError> {
-        let row = sqlx::query("SELECT block_trace FROM block_trace WHERE block_number = ?")
-            .bind(block_number as i64)
-            .fetch_optional(&self.pool)
+        let row =
+            sqlx::query("SELECT block_hash, block_trace FROM block_trace WHERE block_number = ?")
+                .bind(block_number as i64)
+                .fetch_optional(&self.reader)
+                .await
+                .map_err(DatabaseError::GetTrace)?;
+
+        if let Some(row) = row {
+            let block_trace: String = row.try_get("block_trace").expect("column is well formed");
+            let mut block_trace: BlockTrace =
+                serde_json::from_str(&block_trace).map_err(DatabaseError::MalformedTrace)?;
+            let block_hash: String = row.try_get("block_hash").expect("column is well formed");
+            self.load_trie_nodes(&block_hash, &mut block_trace).await?;
+            self.load_code(&mut block_trace).await?;
+            Ok(Some(block_trace))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn get_raw_block_trace_by_hash(
+        &self,
+        block_hash: FixedBytes<32>,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        let row = sqlx::query("SELECT block_trace FROM block_trace WHERE block_hash = ?")
+            .bind(block_hash.to_string())
+            .fetch_optional(&self.reader)
             .await
             .map_err(DatabaseError::GetTrace)?;
 
         if let Some(row) = row {
             let block_trace: String = row.try_get("block_trace").expect("column is well formed");
             Ok(Some(
-                serde_json::from_str(&block_trace).expect("block trace is deserializable"),
+                self.load_raw(&block_hash.to_string(), &block_trace).await?,
             ))
         } else {
             Ok(None)
         }
     }
 
+    async fn get_raw_block_trace_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<Box<RawValue>>, DatabaseError> {
+        let row =
+            sqlx::query("SELECT block_hash, block_trace FROM block_trace WHERE block_number = ?")
+                .bind(block_number as i64)
+                .fetch_optional(&self.reader)
+                .await
+                .map_err(DatabaseError::GetTrace)?;
+
+        if let Some(row) = row {
+            let block_hash: String = row.try_get("block_hash").expect("column is well formed");
+            let block_trace: String = row.try_get("block_trace").expect("column is well formed");
+            Ok(Some(self.load_raw(&block_hash, &block_trace).await?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn get_block_hash_by_number(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<FixedBytes<32>>, DatabaseError> {
+        let row = sqlx::query("SELECT block_hash FROM block_trace WHERE block_number = ?")
+            .bind(block_number as i64)
+            .fetch_optional(&self.reader)
+            .await
+            .map_err(DatabaseError::GetTrace)?;
+
+        Ok(row.map(|row| {
+            let block_hash: String = row.try_get("block_hash").expect("column is well formed");
+            block_hash.parse().expect("block hash is well formed")
+        }))
+    }
+
+    async fn get_highest_block_number(&self) -> Result<Option<u64>, DatabaseError> {
+        let row = sqlx::query("SELECT MAX(block_number) AS block_number FROM block_trace")
+            .fetch_one(&self.reader)
+            .await
+            .map_err(DatabaseError::GetTrace)?;
+
+        let block_number: Option<i64> = row.try_get("block_number").expect("column is well formed");
+        Ok(block_number.map(|block_number| block_number as u64))
+    }
+
     async fn delete_block_trace_by_hash(
         &self,
         block_hash: FixedBytes<32>,
     ) -> Result<(), DatabaseError> {
+        let mut tx = self
+            .writer
+            .begin()
+            .await
+            .map_err(DatabaseError::DeleteTrace)?;
+        release_trie_nodes(&mut tx, &block_hash.to_string())
+            .await
+            .map_err(DatabaseError::DeleteTrace)?;
         sqlx::query("DELETE FROM block_trace WHERE block_hash = ?1")
             .bind(block_hash.to_string())
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await
             .map_err(DatabaseError::DeleteTrace)?;
+        tx.commit().await.map_err(DatabaseError::DeleteTrace)?;
         Ok(())
     }
+
+    async fn delete_block_traces_before(&self, before: u64) -> Result<u64, DatabaseError> {
+        let mut tx = self
+            .writer
+            .begin()
+            .await
+            .map_err(DatabaseError::DeleteTrace)?;
+        release_trie_nodes_before(&mut tx, before as i64)
+            .await
+            .map_err(DatabaseError::DeleteTrace)?;
+        let deleted = sqlx::query("DELETE FROM block_trace WHERE block_number < ?")
+            .bind(before as i64)
+            .execute(&mut *tx)
+            .await
+            .map_err(DatabaseError::DeleteTrace)?;
+        tx.commit().await.map_err(DatabaseError::DeleteTrace)?;
+        Ok(deleted.rows_affected())
+    }
+}
+
+/// Store the trie nodes of a block once and reference them from the block, in
+/// chunks of at most [`MAX_BIND_PARAMS`] bound values per statement.
+async fn insert_trie_nodes(
+    tx: &mut Transaction<'_, sqlx::Sqlite>,
+    block_hash: &str,
+    nodes: HashMap<H256, Vec<u8>>,
+) -> Result<(), sqlx::Error> {
+    let nodes: Vec<_> = nodes
+        .into_iter()
+        .map(|(hash, node)| (format!("{hash:?}"), node))
+        .collect();
+    for chunk in nodes.chunks(MAX_BIND_PARAMS / 2) {
+        QueryBuilder::new("INSERT OR IGNORE INTO trie_node (node_hash, node) ")
+            .push_values(chunk, |mut row, (hash, node)| {
+                row.push_bind(hash.as_str()).push_bind(node.as_slice());
+            })
+            .build()
+            .execute(&mut **tx)
+            .await?;
+        QueryBuilder::new("INSERT OR IGNORE INTO block_trie_node (block_hash, node_hash) ")
+            .push_values(chunk, |mut row, (hash, _)| {
+                row.push_bind(block_hash).push_bind(hash.as_str());
+            })
+            .build()
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Drop the trie node references of a block, deleting the nodes which are no
+/// longer referenced by any other block.
+async fn release_trie_nodes(
+    tx: &mut Transaction<'_, sqlx::Sqlite>,
+    block_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM trie_node WHERE node_hash IN (
+            SELECT node_hash FROM block_trie_node WHERE block_hash = ?1
+        ) AND NOT EXISTS (
+            SELECT 1 FROM block_trie_node AS other
+            WHERE other.node_hash = trie_node.node_hash AND other.block_hash != ?1
+        )",
+    )
+    .bind(block_hash)
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query("DELETE FROM block_trie_node WHERE block_hash = ?")
+        .bind(block_hash)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Drop the trie node references of all blocks below `before`, deleting the
+/// nodes which are no longer referenced by any later block.
+async fn release_trie_nodes_before(
+    tx: &mut Transaction<'_, sqlx::Sqlite>,
+    before: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM trie_node WHERE node_hash IN (
+            SELECT node_hash FROM block_trie_node WHERE block_hash IN (
+                SELECT block_hash FROM block_trace WHERE block_number < ?1
+            )
+        ) AND NOT EXISTS (
+            SELECT 1 FROM block_trie_node AS other
+            WHERE other.node_hash = trie_node.node_hash AND other.block_hash NOT IN (
+                SELECT block_hash FROM block_trace WHERE block_number < ?1
+            )
+        )",
+    )
+    .bind(before)
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query(
+        "DELETE FROM block_trie_node WHERE block_hash IN (
+            SELECT block_hash FROM block_trace WHERE block_number < ?
+        )",
+    )
+    .bind(before)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Create the parent directories of the database file and check that the file
+/// can be written, so a misconfigured path fails with a clear error.
+fn ensure_writable(path: &Path) -> Result<(), DatabaseError> {
+    if path == Path::new(":memory:") {
+        return Ok(());
+    }
+    let not_writable = |err| DatabaseError::NotWritable(path.to_path_buf(), err);
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).map_err(not_writable)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(not_writable)?;
+    Ok(())
 }
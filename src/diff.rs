@@ -0,0 +1,377 @@
+//! Differential testing of zero traces against reth's prestate tracer.
+//!
+//! For every transaction of a block the stored `TxnTrace` entries are compared
+//! with the accounts and storage slots reported by `prestateTracer`, and the
+//! balances, nonces, storage writes, code and deletions reported by the
+//! tracer's diff mode.
+use compat::Compat;
+use eyre::eyre;
+use jsonrpsee::{
+    core::client::ClientT,
+    http_client::{HttpClient, HttpClientBuilder},
+    rpc_params,
+};
+use reth_primitives::{Address, BlockNumberOrTag, Bytes, B256, U256};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use trace_decoder::{BlockTrace, ContractCodeUsage, TxnInfo, TxnTrace};
+
+use super::rpc::ZeroTracerRpcApiClient;
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct DiffTraceCommand {
+    /// The HTTP RPC url of the zeth node
+    #[arg(long = "rpc-url", default_value = "http://127.0.0.1:8545")]
+    rpc_url: String,
+    /// The first block to compare
+    #[arg(long = "from")]
+    from: u64,
+    /// The last block to compare, defaults to the first block
+    #[arg(long = "to")]
+    to: Option<u64>,
+}
+
+/// A per transaction result of `debug_traceBlockByNumber`.
+#[derive(Debug, Deserialize)]
+struct TraceResult<T> {
+    result: T,
+}
+
+/// The state of an account as reported by the prestate tracer.
+#[derive(Debug, Default, Deserialize)]
+struct AccountState {
+    balance: Option<U256>,
+    nonce: Option<u64>,
+    code: Option<Bytes>,
+    #[serde(default)]
+    storage: BTreeMap<B256, B256>,
+}
+
+/// The prestate tracer output in diff mode.
+#[derive(Debug, Deserialize)]
+struct PrestateDiff {
+    pre: HashMap<Address, AccountState>,
+    post: HashMap<Address, AccountState>,
+}
+
+impl DiffTraceCommand {
+    /// Compare the traces of the configured block range.
+    pub(crate) async fn execute(self) -> eyre::Result<()> {
+        let client = HttpClientBuilder::default()
+            .max_response_size(u32::MAX)
+            .build(&self.rpc_url)?;
+
+        let mut failed = 0;
+        for number in self.from..=self.to.unwrap_or(self.from) {
+            let mismatches = diff_block(&client, number).await?;
+            if mismatches.is_empty() {
+                println!("Block {number}: traces match");
+                continue;
+            }
+            failed += 1;
+            for mismatch in mismatches {
+                eprintln!("Block {number}: {mismatch}");
+            }
+        }
+
+        if failed > 0 {
+            return Err(eyre!("{failed} blocks have mismatching traces"));
+        }
+        Ok(())
+    }
+}
+
+/// Compare the zero trace of a block against the prestate tracer, returning
+/// a description of every mismatch.
+async fn diff_block(client: &HttpClient, number: u64) -> eyre::Result<Vec<String>> {
+    let block_number = BlockNumberOrTag::Number(number);
+    let block_trace: BlockTrace =
+        serde_json::from_str(client.zero_trace_block_by_number(block_number).await?.get())?;
+    let prestates: Vec<TraceResult<HashMap<Address, AccountState>>> = client
+        .request(
+            "debug_traceBlockByNumber",
+            rpc_params![block_number, json!({ "tracer": "prestateTracer" })],
+        )
+        .await?;
+    let diffs: Vec<TraceResult<PrestateDiff>> = client
+        .request(
+            "debug_traceBlockByNumber",
+            rpc_params![
+                block_number,
+                json!({ "tracer": "prestateTracer", "tracerConfig": { "diffMode": true } })
+            ],
+        )
+        .await?;
+
+    // synthetic system call and post-block entries carry no transaction payload
+    let txn_infos: Vec<&TxnInfo> = block_trace
+        .txn_info
+        .iter()
+        .filter(|info| !info.meta.byte_code.is_empty())
+        .collect();
+    if txn_infos.len() != prestates.len() || txn_infos.len() != diffs.len() {
+        return Ok(vec![format!(
+            "trace has {} transactions, prestate tracer has {}",
+            txn_infos.len(),
+            prestates.len()
+        )]);
+    }
+
+    let mut mismatches = vec![];
+    for (idx, ((info, prestate), diff)) in
+        txn_infos.into_iter().zip(prestates).zip(diffs).enumerate()
+    {
+        diff_accessed(idx, info, &prestate.result, &mut mismatches);
+        diff_post_state(idx, info, &diff.result, &mut mismatches);
+    }
+    Ok(mismatches)
+}
+
+/// Whether a trace destroys its account. The storage accesses of accounts
+/// destroyed in their creating transaction are not traced, as none of their
+/// state outlives the transaction.
+fn is_destroyed(trace: &TxnTrace) -> bool {
+    trace.self_destructed.unwrap_or_default()
+}
+
+/// Whether a trace creates its account. Created accounts start with empty
+/// storage, so their reads and zero writes are not traced.
+fn is_created(trace: &TxnTrace) -> bool {
+    matches!(trace.code_usage, Some(ContractCodeUsage::Write(_)))
+}
+
+/// Compare the touched accounts and accessed storage slots.
+fn diff_accessed(
+    idx: usize,
+    info: &TxnInfo,
+    prestate: &HashMap<Address, AccountState>,
+    mismatches: &mut Vec<String>,
+) {
+    for (address, state) in prestate {
+        let Some(trace) = info.traces.get(&address.compat()) else {
+            mismatches.push(format!("tx {idx}: account {address} missing from trace"));
+            continue;
+        };
+        let expected: HashSet<primitive_types::H256> =
+            state.storage.keys().map(|slot| slot.compat()).collect();
+        let actual: HashSet<primitive_types::H256> = trace
+            .storage_read
+            .iter()
+            .flatten()
+            .copied()
+            .chain(
+                trace
+                    .storage_written
+                    .iter()
+                    .flat_map(|written| written.keys().copied()),
+            )
+            .collect();
+        let only_one: Vec<_> = match is_created(trace) || is_destroyed(trace) {
+            // slots accessed by the prestate tracer may be missing from the trace
+            true => actual.difference(&expected).collect(),
+            false => expected.symmetric_difference(&actual).collect(),
+        };
+        for slot in only_one {
+            mismatches.push(format!(
+                "tx {idx}: account {address} storage slot {slot:?} accessed by only one tracer"
+            ));
+        }
+    }
+}
+
+/// Compare the balances, nonces, storage writes, code and deletions.
+fn diff_post_state(idx: usize, info: &TxnInfo, diff: &PrestateDiff, mismatches: &mut Vec<String>) {
+    for (address, post) in &diff.post {
+        let Some(trace) = info.traces.get(&address.compat()) else {
+            mismatches.push(format!(
+                "tx {idx}: modified account {address} missing from trace"
+            ));
+            continue;
+        };
+
+        if let Some(balance) = post.balance {
+            if trace.balance != Some(balance.compat()) {
+                mismatches.push(format!(
+                    "tx {idx}: account {address} balance {balance} != {:?}",
+                    trace.balance
+                ));
+            }
+        }
+
+        if let Some(nonce) = post.nonce {
+            if trace.nonce != Some(nonce.into()) {
+                mismatches.push(format!(
+                    "tx {idx}: account {address} nonce {nonce} != {:?}",
+                    trace.nonce
+                ));
+            }
+        }
+
+        if is_destroyed(trace) {
+            continue;
+        }
+
+        for (slot, value) in &post.storage {
+            let written = trace
+                .storage_written
+                .as_ref()
+                .and_then(|written| written.get(&slot.compat()).copied());
+            if written != Some(U256::from_be_bytes(value.0).compat()) {
+                mismatches.push(format!(
+                    "tx {idx}: account {address} storage slot {slot} value {value} != {written:?}"
+                ));
+            }
+        }
+
+        if let Some(code) = &post.code {
+            match &trace.code_usage {
+                Some(ContractCodeUsage::Write(written)) if written[..] == code[..] => {}
+                _ => mismatches.push(format!("tx {idx}: account {address} code write mismatch")),
+            }
+        }
+    }
+
+    // accounts which only appear in the pre state have been deleted
+    for address in diff
+        .pre
+        .keys()
+        .filter(|address| !diff.post.contains_key(*address))
+    {
+        let self_destructed = info
+            .traces
+            .get(&address.compat())
+            .and_then(|trace| trace.self_destructed)
+            .unwrap_or_default();
+        if !self_destructed {
+            mismatches.push(format!(
+                "tx {idx}: deleted account {address} not self destructed"
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trace_decoder::TxnMeta;
+
+    fn slot(slot: u64) -> B256 {
+        B256::from(U256::from(slot))
+    }
+
+    fn trace(balance: u64) -> TxnTrace {
+        TxnTrace {
+            balance: Some(U256::from(balance).compat()),
+            nonce: Some(1.into()),
+            storage_read: None,
+            storage_written: None,
+            code_usage: None,
+            self_destructed: None,
+        }
+    }
+
+    fn txn_info(traces: impl IntoIterator<Item = (Address, TxnTrace)>) -> TxnInfo {
+        TxnInfo {
+            meta: TxnMeta {
+                byte_code: vec![0x01],
+                new_receipt_trie_node_byte: vec![],
+                gas_used: 21_000,
+            },
+            traces: traces
+                .into_iter()
+                .map(|(address, trace)| (address.compat(), trace))
+                .collect(),
+        }
+    }
+
+    fn account(balance: u64, storage: impl IntoIterator<Item = (B256, B256)>) -> AccountState {
+        AccountState {
+            balance: Some(U256::from(balance)),
+            nonce: Some(1),
+            code: None,
+            storage: storage.into_iter().collect(),
+        }
+    }
+
+    fn diff(
+        info: &TxnInfo,
+        prestate: HashMap<Address, AccountState>,
+        post: HashMap<Address, AccountState>,
+    ) -> Vec<String> {
+        let mut mismatches = vec![];
+        diff_accessed(0, info, &prestate, &mut mismatches);
+        let pre = prestate;
+        diff_post_state(0, info, &PrestateDiff { pre, post }, &mut mismatches);
+        mismatches
+    }
+
+    #[test]
+    fn matching_traces_have_no_mismatches() {
+        let address = Address::repeat_byte(0x11);
+        let mut trace = trace(5);
+        trace.storage_read = Some(vec![slot(1).compat()]);
+        trace.storage_written = Some(HashMap::from([(slot(2).compat(), 7.into())]));
+
+        let mismatches = diff(
+            &txn_info([(address, trace)]),
+            HashMap::from([(
+                address,
+                account(9, [(slot(1), slot(3)), (slot(2), slot(0))]),
+            )]),
+            HashMap::from([(address, account(5, [(slot(2), slot(7))]))]),
+        );
+        assert!(mismatches.is_empty(), "{mismatches:?}");
+    }
+
+    #[test]
+    fn mismatching_balance_and_slots_are_reported() {
+        let address = Address::repeat_byte(0x11);
+
+        let mismatches = diff(
+            &txn_info([(address, trace(4))]),
+            HashMap::from([(address, account(9, [(slot(1), slot(3))]))]),
+            HashMap::from([(address, account(5, []))]),
+        );
+        assert_eq!(mismatches.len(), 2, "{mismatches:?}");
+    }
+
+    #[test]
+    fn created_account_reads_are_not_traced() {
+        let address = Address::repeat_byte(0x11);
+        let mut trace = trace(5);
+        trace.code_usage = Some(ContractCodeUsage::Write(vec![0x00]));
+        trace.storage_written = Some(HashMap::from([(slot(2).compat(), 7.into())]));
+
+        let mut post = account(5, [(slot(2), slot(7))]);
+        post.code = Some(Bytes::from_static(&[0x00]));
+        let mismatches = diff(
+            &txn_info([(address, trace)]),
+            // a read of slot 1 and a zero write to slot 3 of the fresh storage
+            HashMap::from([(
+                address,
+                account(
+                    0,
+                    [(slot(1), slot(0)), (slot(2), slot(0)), (slot(3), slot(0))],
+                ),
+            )]),
+            HashMap::from([(address, post)]),
+        );
+        assert!(mismatches.is_empty(), "{mismatches:?}");
+    }
+
+    #[test]
+    fn destroyed_account_storage_is_not_traced() {
+        let address = Address::repeat_byte(0x11);
+        let mut trace = trace(0);
+        trace.nonce = Some(0.into());
+        trace.self_destructed = Some(true);
+
+        let mismatches = diff(
+            &txn_info([(address, trace)]),
+            HashMap::from([(address, account(9, [(slot(1), slot(3))]))]),
+            HashMap::new(),
+        );
+        assert!(mismatches.is_empty(), "{mismatches:?}");
+    }
+}
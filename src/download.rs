@@ -0,0 +1,228 @@
+//! Plain HTTP download of stored block traces.
+//!
+//! `GET /zeth/trace/{number|hash}` serves the stored JSON of a block trace.
+//! Responses are compressed with gzip or zstd as negotiated through
+//! `Accept-Encoding`, and carry a hash of the stored trace as `ETag` so clients
+//! can revalidate with `If-None-Match`. Traces stored as files are streamed from
+//! disk, and compressed files are passed through to clients accepting zstd.
+use async_compression::tokio::bufread::ZstdDecoder;
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use reth_primitives::{keccak256, B256};
+use revm::primitives::alloy_primitives::Keccak256;
+use std::{io, net::SocketAddr, sync::Arc};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio_util::io::ReaderStream;
+use tower_http::compression::CompressionLayer;
+use tracing::{error, info};
+
+use super::db::{Database, TraceDownload};
+
+/// Serve block trace downloads on `addr` until the server fails.
+pub(crate) async fn serve(addr: SocketAddr, db: Arc<dyn Database>) -> eyre::Result<()> {
+    let app = Router::new()
+        .route("/zeth/trace/:block", get(get_trace))
+        .layer(CompressionLayer::new().gzip(true).zstd(true))
+        .with_state(db);
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("Serving block trace downloads on {addr}");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn get_trace(
+    State(db): State<Arc<dyn Database>>,
+    Path(block): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let block_hash = match block.parse::<B256>() {
+        Ok(hash) if block.starts_with("0x") => Ok(Some(hash)),
+        _ => match block.parse::<u64>() {
+            Ok(number) => db.get_block_hash_by_number(number).await,
+            Err(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "expected a block number or block hash",
+                )
+                    .into_response()
+            }
+        },
+    };
+    let trace = match block_hash {
+        Ok(Some(block_hash)) => db.get_block_trace_download(block_hash).await,
+        Ok(None) => Ok(None),
+        Err(err) => Err(err),
+    };
+
+    let mut trace = match trace {
+        Ok(Some(trace)) => trace,
+        Ok(None) => return (StatusCode::NOT_FOUND, "block trace not found").into_response(),
+        Err(err) => {
+            error!("Failed to read block trace {block}: {err}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // a retraced block keeps its hash, so the etag is derived from the content
+    let etag = match content_etag(&mut trace).await {
+        Ok(etag) => etag,
+        Err(err) => {
+            error!("Failed to read block trace {block}: {err}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if if_none_match(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    let content_type = (
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    match trace {
+        TraceDownload::Json(trace) => (
+            [content_type, (header::ETAG, etag)],
+            String::from(Box::<str>::from(trace)),
+        )
+            .into_response(),
+        TraceDownload::File { file, compressed } if compressed && accepts_zstd(&headers) => (
+            [
+                content_type,
+                (header::ETAG, etag),
+                (header::CONTENT_ENCODING, HeaderValue::from_static("zstd")),
+            ],
+            Body::from_stream(ReaderStream::new(file)),
+        )
+            .into_response(),
+        TraceDownload::File { file, compressed } => {
+            let body = match compressed {
+                true => {
+                    Body::from_stream(ReaderStream::new(ZstdDecoder::new(BufReader::new(file))))
+                }
+                false => Body::from_stream(ReaderStream::new(file)),
+            };
+            ([content_type, (header::ETAG, etag)], body).into_response()
+        }
+    }
+}
+
+/// The `ETag` of a trace, the hash of its stored bytes. A trace file is hashed
+/// in chunks and rewound to be streamed.
+async fn content_etag(trace: &mut TraceDownload) -> io::Result<HeaderValue> {
+    let hash = match trace {
+        TraceDownload::Json(trace) => keccak256(trace.get()),
+        TraceDownload::File { file, .. } => {
+            let mut hasher = Keccak256::new();
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let read = file.read(&mut buf).await?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buf[..read]);
+            }
+            file.rewind().await?;
+            hasher.finalize()
+        }
+    };
+    Ok(HeaderValue::from_str(&format!("\"{hash}\"")).expect("etag is a valid header value"))
+}
+
+/// Whether an `If-None-Match` header matches `etag`, using the weak
+/// comparison required for `If-None-Match`.
+fn if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let etag = etag.to_str().expect("etag is ascii");
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Whether the client accepts zstd encoded responses.
+fn accepts_zstd(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            params.next() == Some("zstd")
+                && params.all(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .map_or(true, |q| q > 0.0)
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::value::RawValue;
+
+    fn headers(name: header::HeaderName, value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(name, HeaderValue::from_static(value))])
+    }
+
+    #[test]
+    fn if_none_match_accepts_lists_weak_tags_and_wildcards() {
+        let etag = HeaderValue::from_static("\"0x01\"");
+        let matches = |value| if_none_match(&headers(header::IF_NONE_MATCH, value), &etag);
+
+        assert!(matches("\"0x01\""));
+        assert!(matches("\"0x02\", W/\"0x01\""));
+        assert!(matches("*"));
+        assert!(!matches("\"0x02\""));
+        assert!(!if_none_match(&HeaderMap::new(), &etag));
+    }
+
+    #[test]
+    fn zstd_is_accepted_unless_disabled() {
+        let accepts = |value| accepts_zstd(&headers(header::ACCEPT_ENCODING, value));
+
+        assert!(accepts("gzip, zstd"));
+        assert!(accepts("zstd;q=0.5"));
+        assert!(!accepts("zstd;q=0"));
+        assert!(!accepts("gzip"));
+    }
+
+    #[tokio::test]
+    async fn etag_follows_the_trace_content() {
+        let json = |value: &str| TraceDownload::Json(RawValue::from_string(value.into()).unwrap());
+        let etag = |mut trace| async move { content_etag(&mut trace).await.unwrap() };
+
+        assert_eq!(etag(json("{}")).await, etag(json("{}")).await);
+        assert_ne!(etag(json("{}")).await, etag(json("[]")).await);
+
+        // a file hashes like its content and is rewound to be streamed
+        let path = std::env::temp_dir().join(format!("zeth-etag-{}.json", std::process::id()));
+        std::fs::write(&path, "{}").unwrap();
+        let mut trace = TraceDownload::File {
+            file: tokio::fs::File::open(&path).await.unwrap(),
+            compressed: false,
+        };
+        assert_eq!(
+            content_etag(&mut trace).await.unwrap(),
+            etag(json("{}")).await
+        );
+        let TraceDownload::File { mut file, .. } = trace else {
+            unreachable!()
+        };
+        let mut content = String::new();
+        file.read_to_string(&mut content).await.unwrap();
+        assert_eq!(content, "{}");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -6,6 +6,22 @@ pub enum ZeroApiError {
     TraceNotFoundForBlockHash(String),
     #[error("Database error")]
     DatabaseError(#[from] DatabaseError),
+    #[error("Block not found for block number: {0}")]
+    BlockNotFound(u64),
+    #[error("Block not found for block tag: {0}")]
+    UnknownBlockTag(reth_primitives::BlockNumberOrTag),
+    #[error("Provider error: {0}")]
+    ProviderError(reth_provider::ProviderError),
+    #[error("Block trace verification failed: {0}")]
+    VerificationFailed(VerifyError),
+    #[error("Failed to retrace block {0}: {1}")]
+    RetraceFailed(u64, String),
+    #[error("Invalid block range {0}..={1}")]
+    InvalidRange(u64, u64),
+    #[error("Failed to build block data of block {0}: {1}")]
+    BlockDataFailed(u64, String),
+    #[error("Background task failed: {0}")]
+    TaskFailed(String),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -20,6 +36,88 @@ pub enum DatabaseError {
     CreateTables(sqlx::Error),
     #[error("Failed to open database connection: {0}")]
     OpenConnection(sqlx::Error),
+    #[error("Database file {} is not writable: {1}", .0.display())]
+    NotWritable(std::path::PathBuf, std::io::Error),
+    #[error("Failed to access {}: {1}", .0.display())]
+    Filesystem(std::path::PathBuf, std::io::Error),
+    #[error("Contract code {0:?} referenced by trace is missing from database")]
+    MissingCode(primitive_types::H256),
+    #[error("Malformed stored block trace: {0}")]
+    MalformedTrace(serde_json::Error),
+    #[error("Malformed block trace file {}: {1}", .0.display())]
+    MalformedFile(std::path::PathBuf, serde_json::Error),
+    #[error("Trie node {0:?} referenced by trace is missing from database")]
+    MissingTrieNode(primitive_types::H256),
+    #[error("Malformed trie node below root {0:?}: {1}")]
+    MalformedTrieNode(primitive_types::H256, alloy_rlp::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TracerError {
+    #[error("Block has {transactions} transactions but {receipts} receipts")]
+    ReceiptCountMismatch {
+        transactions: usize,
+        receipts: usize,
+    },
+    #[error("Block has {transactions} transactions but {traces} transaction traces")]
+    TxTraceCountMismatch { transactions: usize, traces: usize },
+    #[error("Receipt missing for transaction {0}")]
+    MissingReceipt(usize),
+    #[error("Cumulative gas used decreases at transaction {index}: {previous} -> {current}")]
+    CumulativeGasDecreased {
+        index: usize,
+        previous: u64,
+        current: u64,
+    },
+    #[error("Gas used mismatch: header reports {header}, receipts sum to {receipts}")]
+    GasUsedMismatch { header: u64, receipts: u64 },
+    #[error("Receipts root mismatch: header reports {header}, receipts hash to {receipts}")]
+    ReceiptsRootMismatch {
+        header: reth_primitives::B256,
+        receipts: reth_primitives::B256,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VerifyError {
+    #[error("Witness is missing trie node {0:?}")]
+    MissingNode(primitive_types::H256),
+    #[error("Witness is missing the storage trie of account {0}")]
+    MissingStorageTrie(reth_primitives::Address),
+    #[error("Witness is missing code for hash {0}")]
+    MissingCode(reth_primitives::B256),
+    #[error("Block hash not available for block number {0}")]
+    MissingBlockHash(u64),
+    #[error("Total difficulty not available for block number {0}")]
+    MissingTotalDifficulty(u64),
+    #[error("Witness does not reveal enough nodes to apply the post state: {0}")]
+    IncompleteWitness(String),
+    #[error("Only separate direct trie pre-images can be verified")]
+    UnsupportedPreImages,
+    #[error("Malformed witness value: {0}")]
+    Rlp(#[from] alloy_rlp::Error),
+    #[error("Block execution failed: {0}")]
+    Execution(String),
+    #[error("Expected {expected} receipts, execution produced {actual}")]
+    ReceiptCountMismatch { expected: usize, actual: usize },
+    #[error("Receipt mismatch for transaction {0}")]
+    ReceiptMismatch(usize),
+    #[error("State root mismatch: expected {expected}, got {actual}")]
+    StateRootMismatch {
+        expected: reth_primitives::B256,
+        actual: reth_primitives::B256,
+    },
+    #[error("Provider error: {0}")]
+    Provider(#[from] reth_provider::ProviderError),
+}
+
+impl From<VerifyError> for reth_provider::ProviderError {
+    fn from(err: VerifyError) -> Self {
+        match err {
+            VerifyError::Provider(err) => err,
+            err => Self::Database(reth_provider::DatabaseError::Other(err.to_string())),
+        }
+    }
 }
 
 impl From<ZeroApiError> for jsonrpsee::types::error::ErrorObject<'static> {
@@ -45,6 +143,24 @@ impl From<ZeroApiError> for jsonrpsee::types::error::ErrorObject<'static> {
                     None::<u8>,
                 )
             }
+
+            ZeroApiError::BlockNotFound(_)
+            | ZeroApiError::UnknownBlockTag(_)
+            | ZeroApiError::InvalidRange(..) => jsonrpsee::types::error::ErrorObject::owned(
+                jsonrpsee::types::error::INVALID_PARAMS_CODE,
+                err.to_string(),
+                None::<u8>,
+            ),
+
+            ZeroApiError::ProviderError(_)
+            | ZeroApiError::VerificationFailed(_)
+            | ZeroApiError::RetraceFailed(..)
+            | ZeroApiError::BlockDataFailed(..)
+            | ZeroApiError::TaskFailed(_) => jsonrpsee::types::error::ErrorObject::owned(
+                jsonrpsee::types::error::INTERNAL_ERROR_CODE,
+                err.to_string(),
+                None::<u8>,
+            ),
         }
     }
 }
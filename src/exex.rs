@@ -1,33 +1,75 @@
-use super::{db::Database, tracer::trace_block};
+use super::{
+    db::Database,
+    status::TracerStatus,
+    tracer::{trace_block, verify_block_trace, ProofCache, TraceContext, TracerConfig},
+};
 use reth_exex::{ExExContext, ExExEvent};
 use reth_node_api::FullNodeComponents;
 use reth_primitives::{Receipt, SealedBlockWithSenders};
+use reth_provider::{BlockNumReader, BlockReader, ChainSpecProvider};
 use revm::{
     db::ExecutionTrace,
     primitives::{Account, Address, HashMap},
 };
 use std::sync::Arc;
-use tracing::info;
+use tokio::sync::mpsc;
+use trace_decoder::BlockTrace;
+use tracing::{error, info, warn};
+
+/// The distance to the canonical tip above which the ExEx is reported as
+/// catching up.
+const BACKFILL_DISTANCE: u64 = 1;
+
+/// The number of traced blocks queued for background verification. Blocks
+/// traced while the queue is full are not verified.
+const VERIFY_QUEUE_SIZE: usize = 16;
+
+/// A traced block queued for background verification.
+type Verification = (SealedBlockWithSenders, Vec<Receipt>, BlockTrace);
 
 /// ZeroTracerExEx
 pub struct ZeroTracerExEx<Node: FullNodeComponents> {
     pub(crate) ctx: ExExContext<Node>,
     pub(crate) db: Arc<dyn Database>,
+    pub(crate) config: TracerConfig,
+    pub(crate) cache: ProofCache,
+    pub(crate) status: Arc<TracerStatus>,
+    /// The queue of the background verification worker, if enabled.
+    pub(crate) verifier: Option<mpsc::Sender<Verification>>,
 }
 
 impl<Node: FullNodeComponents> ZeroTracerExEx<Node> {
     /// Construct a new ZeroTracerExEx instance.
-    pub fn new(ctx: ExExContext<Node>, db: Arc<dyn Database>) -> eyre::Result<Self> {
-        Ok(Self { ctx, db })
+    pub fn new(
+        ctx: ExExContext<Node>,
+        db: Arc<dyn Database>,
+        config: TracerConfig,
+        status: Arc<TracerStatus>,
+    ) -> eyre::Result<Self> {
+        let cache = ProofCache::new(config.proof_cache_size);
+        let verifier = config
+            .verify_witness
+            .then(|| spawn_verifier(ctx.provider().clone()));
+        Ok(Self {
+            ctx,
+            db,
+            config,
+            cache,
+            status,
+            verifier,
+        })
     }
 
     /// Run the ZeroTracerExEx.
     pub async fn run(mut self) -> eyre::Result<()> {
+        let status = self.status.clone();
+        let _running = status.start();
         while let Some(notification) = self.ctx.notifications.recv().await {
             if let Some(reverted_chain) = notification.reverted_chain() {
                 for block in reverted_chain.blocks().values() {
                     self.revert_block(block).await?;
                 }
+                status.set_latest_traced(reverted_chain.first().number.saturating_sub(1));
             }
 
             if let Some(committed_chain) = notification.committed_chain() {
@@ -63,13 +105,59 @@ impl<Node: FullNodeComponents> ZeroTracerExEx<Node> {
         let block_number = block.header().number;
         let block_hash = block.hash();
         info!("Processing block {} - {}", block_number, block_hash);
-        let block_trace = trace_block(&mut self.ctx, block, receipts, trace, tx_traces)?;
+        // blocks are only cloned for verification if the queue has room
+        let verification = self
+            .verifier
+            .as_ref()
+            .filter(|verifier| verifier.capacity() > 0)
+            .map(|_| (block.clone(), receipts.iter().flatten().cloned().collect()));
+        let block_trace = trace_block(
+            TraceContext {
+                provider: self.ctx.provider(),
+                chain_spec: &self.ctx.config.chain,
+                config: &self.config,
+                cache: &mut self.cache,
+            },
+            block,
+            receipts,
+            trace,
+            tx_traces,
+        )?;
+        if let Some((block, receipts)) = verification {
+            self.queue_verification(block, receipts, block_trace.clone());
+        } else if self.verifier.is_some() {
+            warn!(
+                "Verification queue is full, skipping block {}",
+                block_number
+            );
+        }
         self.db
             .commit_block_trace(block_hash, block_number, block_trace)
             .await?;
+        self.status.set_latest_traced(block_number);
+        let canonical_tip = self.ctx.provider().best_block_number()?;
+        self.status
+            .set_backfilling(canonical_tip.saturating_sub(block_number) > BACKFILL_DISTANCE);
         Ok(())
     }
 
+    /// Queue a block for re-execution against its witness, skipping it if the
+    /// queue is full.
+    fn queue_verification(
+        &self,
+        block: SealedBlockWithSenders,
+        receipts: Vec<Receipt>,
+        block_trace: BlockTrace,
+    ) {
+        let Some(verifier) = &self.verifier else {
+            return;
+        };
+        let number = block.number;
+        if verifier.try_send((block, receipts, block_trace)).is_err() {
+            warn!("Verification queue is full, skipping block {}", number);
+        }
+    }
+
     /// Process a block revert.
     pub(crate) async fn revert_block(
         &mut self,
@@ -77,7 +165,36 @@ impl<Node: FullNodeComponents> ZeroTracerExEx<Node> {
     ) -> eyre::Result<()> {
         let block_hash = block.hash();
         info!("Reverting block {}", block_hash);
+        self.cache.clear();
         self.db.delete_block_trace_by_hash(block_hash).await?;
         Ok(())
     }
 }
+
+/// Spawn the worker re-executing queued blocks against their witnesses, one
+/// block at a time on a blocking task.
+fn spawn_verifier<P>(provider: P) -> mpsc::Sender<Verification>
+where
+    P: BlockReader + ChainSpecProvider + Clone + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel::<Verification>(VERIFY_QUEUE_SIZE);
+    tokio::spawn(async move {
+        while let Some((block, receipts, block_trace)) = receiver.recv().await {
+            let provider = provider.clone();
+            let number = block.number;
+            let verified = tokio::task::spawn_blocking(move || {
+                verify_block_trace(&provider, &block, &receipts, &block_trace)
+            })
+            .await;
+            match verified {
+                Ok(Ok(())) => info!("Verified witness for block {}", number),
+                Ok(Err(err)) => error!("Witness verification failed for block {}: {}", number, err),
+                Err(err) => error!(
+                    "Witness verification task failed for block {}: {}",
+                    number, err
+                ),
+            }
+        }
+    });
+    sender
+}
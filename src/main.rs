@@ -1,40 +1,133 @@
 //! Polygon Zero exex
 use clap::Parser;
-use db::{Database, Postgres, Sqlite};
-use std::sync::Arc;
+use cli::ZethCli;
+use db::{
+    Cached, Composite, Database, DatabaseConfig, DatabaseType, JournalMode, Synchronous,
+    TraceLayout,
+};
+use status::TracerStatus;
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Duration};
+use tracer::{PreImageFormat, TracerConfig};
 
+mod admin;
+mod archive;
+mod cli;
 mod db;
+mod diff;
+mod download;
 mod error;
 mod exex;
 mod rpc;
+mod status;
 mod tracer;
 
 pub const DEFAULT_SQLITE_PATH: &str = "polygon-zero.db";
+pub const DEFAULT_FS_DIR: &str = "witnesses";
 pub const DEFAULT_PG_URL: &str = "postgres://postgres@localhost:5432/postgres";
 
 #[derive(Debug, Clone, Default, PartialEq, Eq, clap::Args)]
 #[command(next_help_heading = "Zero Args")]
 struct Args {
-    /// The database type to be used for the zero tracer
+    /// The database types to be used for the zero tracer, traces are written to all of them and read from the first holding them
     #[arg(
         long = "zeth.db-type",
         value_name = "DATABASE_TYPE",
+        value_delimiter = ',',
         default_value = "sqlite"
     )]
-    pub db_type: DatabaseType,
-    /// The path / url to the database
+    pub db_type: Vec<DatabaseType>,
+    /// The path / url of a database, repeated for each database in the order of the database types
     #[arg(long = "zeth.db-path", value_name = "DATABASE_PATH")]
-    pub db_path: Option<String>,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Eq, clap::ValueEnum)]
-enum DatabaseType {
-    #[default]
-    Sqlite,
-    Postgres,
+    pub db_path: Vec<String>,
+    /// The storage layout of block traces, deduplicated stores trie nodes shared between blocks once
+    #[arg(
+        long = "zeth.db-layout",
+        value_name = "DATABASE_LAYOUT",
+        default_value = "inline"
+    )]
+    pub db_layout: TraceLayout,
+    /// The maximum number of database connections serving reads
+    #[arg(
+        long = "zeth.db-read-connections",
+        value_name = "CONNECTIONS",
+        default_value_t = 4
+    )]
+    pub db_read_connections: u32,
+    /// The maximum number of database connections committing traces, SQLite always uses one
+    #[arg(
+        long = "zeth.db-write-connections",
+        value_name = "CONNECTIONS",
+        default_value_t = 1
+    )]
+    pub db_write_connections: u32,
+    /// Seconds to wait for a pooled database connection
+    #[arg(
+        long = "zeth.db-acquire-timeout",
+        value_name = "SECONDS",
+        default_value_t = 30
+    )]
+    pub db_acquire_timeout: u64,
+    /// Seconds an unused database connection is kept open, 0 keeps connections open
+    #[arg(
+        long = "zeth.db-idle-timeout",
+        value_name = "SECONDS",
+        default_value_t = 600
+    )]
+    pub db_idle_timeout: u64,
+    /// Milliseconds after which a Postgres statement is aborted or a busy SQLite database gives up, 0 disables the timeout
+    #[arg(
+        long = "zeth.db-statement-timeout",
+        value_name = "MILLISECONDS",
+        default_value_t = 0
+    )]
+    pub db_statement_timeout: u64,
+    /// The SQLite journal mode
+    #[arg(
+        long = "zeth.db-journal-mode",
+        value_name = "JOURNAL_MODE",
+        default_value = "wal"
+    )]
+    pub db_journal_mode: JournalMode,
+    /// The SQLite synchronous setting
+    #[arg(
+        long = "zeth.db-synchronous",
+        value_name = "SYNCHRONOUS",
+        default_value = "normal"
+    )]
+    pub db_synchronous: Synchronous,
+    /// The size of the in-memory cache of decoded block traces in MiB, 0 disables the cache
+    #[arg(long = "zeth.db-cache-size", value_name = "MIB", default_value_t = 0)]
+    pub db_cache_size: usize,
+    /// Compress traces written by the filesystem database with zstd
+    #[arg(long = "zeth.db-compress", default_value_t = false)]
+    pub db_compress: bool,
+    /// The trie pre-image format emitted in block traces
+    #[arg(
+        long = "zeth.pre-image-format",
+        value_name = "PRE_IMAGE_FORMAT",
+        default_value = "separate"
+    )]
+    pub pre_image_format: PreImageFormat,
+    /// Prune witness subtrees that are not traversed by the prover
+    #[arg(long = "zeth.minimize-witness", default_value_t = false)]
+    pub minimize_witness: bool,
+    /// The number of hot accounts to cache storage proofs for across blocks, 0 disables the cache
+    #[arg(
+        long = "zeth.proof-cache-size",
+        value_name = "ACCOUNTS",
+        default_value_t = 0
+    )]
+    pub proof_cache_size: usize,
+    /// The socket address serving block trace downloads over plain HTTP, disabled if unset
+    #[arg(long = "zeth.http-addr", value_name = "SOCKET_ADDR")]
+    pub http_addr: Option<SocketAddr>,
+    /// Re-execute every traced block against its witness in the background
+    #[arg(long = "zeth.verify-witness", default_value_t = false)]
+    pub verify_witness: bool,
 }
 
 fn main() {
+    use admin::{ZeroAdminRpc, ZeroAdminRpcApiServer};
     use exex::ZeroTracerExEx;
     use reth::cli::Cli;
     use reth_node_ethereum::EthereumNode;
@@ -45,17 +138,59 @@ fn main() {
         std::env::set_var("RUST_BACKTRACE", "1");
     }
 
+    if ZethCli::is_selected() {
+        if let Err(err) = ZethCli::parse().run() {
+            eprintln!("Error: {err:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     if let Err(err) = Cli::<Args>::parse().run(|builder, args| async move {
-        let db = init_db(args).await?;
+        if args.verify_witness && args.pre_image_format == PreImageFormat::Combined {
+            eyre::bail!("--zeth.verify-witness requires the separate pre-image format");
+        }
+        let tracer_config = TracerConfig {
+            pre_image_format: args.pre_image_format,
+            minimize_witness: args.minimize_witness,
+            proof_cache_size: args.proof_cache_size,
+            verify_witness: args.verify_witness,
+        };
+        let http_addr = args.http_addr;
+        let status = Arc::new(TracerStatus::new(
+            args.db_type
+                .iter()
+                .map(|db_type| format!("{db_type:?}").to_lowercase())
+                .collect::<Vec<_>>()
+                .join(","),
+        ));
+        let exex_status = status.clone();
+        let datadir = builder.config().datadir().data_dir().to_path_buf();
+        let db = init_db(args, &datadir).await?;
+        if let Some(block_number) = db.get_highest_block_number().await? {
+            status.set_latest_traced(block_number);
+        }
+        if let Some(addr) = http_addr {
+            let download_db = db.clone();
+            tokio::spawn(async move {
+                if let Err(err) = download::serve(addr, download_db).await {
+                    tracing::error!("Block trace download server failed: {err:?}");
+                }
+            });
+        }
         let exex_db = db.clone();
         let handle = builder
             .node(EthereumNode::default())
             .install_exex("ZeroTracerExEx", move |ctx| async move {
-                let exex = ZeroTracerExEx::new(ctx, exex_db)?;
+                let exex = ZeroTracerExEx::new(ctx, exex_db, tracer_config, exex_status)?;
                 Ok(exex.run())
             })
             .extend_rpc_modules(move |ctx| {
-                let zero_rpc = ZeroTracerRpc::new(db)?;
+                let admin_rpc =
+                    ZeroAdminRpc::new(db.clone(), ctx.provider().clone(), tracer_config);
+                ctx.modules.merge_ipc(admin_rpc.clone().into_rpc())?;
+                ctx.auth_module.merge_auth_methods(admin_rpc.into_rpc())?;
+                let zero_rpc = ZeroTracerRpc::new(db, ctx.provider().clone(), status)?;
                 ctx.modules.merge_configured(zero_rpc.into_rpc())?;
                 Ok(())
             })
@@ -69,13 +204,48 @@ fn main() {
     }
 }
 
-async fn init_db(args: Args) -> eyre::Result<Arc<dyn Database>> {
-    Ok(match args.db_type {
-        DatabaseType::Postgres => {
-            Arc::new(Postgres::new(&args.db_path.unwrap_or(String::from(DEFAULT_PG_URL))).await?)
-        }
-        DatabaseType::Sqlite => {
-            Arc::new(Sqlite::new(&args.db_path.unwrap_or(String::from(DEFAULT_SQLITE_PATH))).await?)
-        }
+/// Open the configured databases, combining several into a [`Composite`]. The
+/// default SQLite database and filesystem archive are placed in the chain
+/// specific reth data directory.
+async fn init_db(args: Args, datadir: &Path) -> eyre::Result<Arc<dyn Database>> {
+    let config = DatabaseConfig {
+        layout: args.db_layout,
+        read_connections: args.db_read_connections,
+        write_connections: args.db_write_connections,
+        acquire_timeout: Duration::from_secs(args.db_acquire_timeout),
+        idle_timeout: Some(Duration::from_secs(args.db_idle_timeout))
+            .filter(|_| args.db_idle_timeout > 0),
+        statement_timeout: Some(Duration::from_millis(args.db_statement_timeout))
+            .filter(|_| args.db_statement_timeout > 0),
+        journal_mode: args.db_journal_mode,
+        synchronous: args.db_synchronous,
+        compress: args.db_compress,
+    };
+    if args.db_path.len() > args.db_type.len() {
+        eyre::bail!(
+            "{} database paths given for {} database types",
+            args.db_path.len(),
+            args.db_type.len()
+        );
+    }
+
+    let mut paths = args.db_path.into_iter();
+    let mut sinks = vec![];
+    for db_type in args.db_type {
+        let path = paths.next().unwrap_or_else(|| match db_type {
+            DatabaseType::Sqlite => datadir.join(DEFAULT_SQLITE_PATH).to_string_lossy().into(),
+            DatabaseType::Postgres => String::from(DEFAULT_PG_URL),
+            DatabaseType::Filesystem => datadir.join(DEFAULT_FS_DIR).to_string_lossy().into(),
+        });
+        sinks.push(db::open(db_type, &path, &config).await?);
+    }
+
+    let db: Arc<dyn Database> = match sinks.len() {
+        1 => sinks.remove(0),
+        _ => Arc::new(Composite::new(sinks)),
+    };
+    Ok(match args.db_cache_size {
+        0 => db,
+        size => Arc::new(Cached::new(db, size * 1024 * 1024)),
     })
 }
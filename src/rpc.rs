@@ -1,60 +1,135 @@
 use jsonrpsee::{core::RpcResult, proc_macros::rpc};
 use reth_primitives::{BlockNumberOrTag, B256};
+use reth_provider::{BlockIdReader, BlockReader, ChainSpecProvider};
+use serde_json::value::RawValue;
 use std::sync::Arc;
-use trace_decoder::BlockTrace;
+use trace_decoder::OtherBlockData;
 
-use super::{db::Database, error::ZeroApiError};
+use super::{
+    db::Database,
+    error::ZeroApiError,
+    status::{TracerStatus, ZeroStatus},
+    tracer::block_data,
+};
 
 /// trait interface for zero tracer rpc.
 ///
 /// This defines an additional zero namespace where all zero tracer methods are defined.
-#[rpc(server, namespace = "zero")]
+#[rpc(server, client, namespace = "zero")]
 pub trait ZeroTracerRpcApi {
     /// Get block trace by block number.
+    ///
+    /// The trace is served as stored, without being decoded.
     #[method(name = "getBlockTraceByNumber")]
-    async fn zero_trace_block_by_number(&self, number: BlockNumberOrTag) -> RpcResult<BlockTrace>;
+    async fn zero_trace_block_by_number(
+        &self,
+        number: BlockNumberOrTag,
+    ) -> RpcResult<Box<RawValue>>;
 
     /// Get block trace by block hash.
     #[method(name = "getBlockTraceByHash")]
-    async fn zero_trace_block_by_hash(&self, hash: B256) -> RpcResult<BlockTrace>;
+    async fn zero_trace_block_by_hash(&self, hash: B256) -> RpcResult<Box<RawValue>>;
+
+    /// Get the block level prover inputs, including the withdrawals, by block
+    /// number.
+    #[method(name = "getBlockDataByNumber")]
+    async fn zero_block_data_by_number(
+        &self,
+        number: BlockNumberOrTag,
+    ) -> RpcResult<OtherBlockData>;
+
+    /// Get the health of the tracer subsystem.
+    #[method(name = "status")]
+    async fn zero_status(&self) -> RpcResult<ZeroStatus>;
+}
+
+/// Resolve a block number or tag to a canonical block number.
+pub(crate) fn block_number<Provider: BlockIdReader>(
+    provider: &Provider,
+    number: BlockNumberOrTag,
+) -> Result<u64, ZeroApiError> {
+    provider
+        .convert_block_number(number)
+        .map_err(ZeroApiError::ProviderError)?
+        .ok_or(ZeroApiError::UnknownBlockTag(number))
 }
 
 /// Zero Tracer RPC implementation.
-pub struct ZeroTracerRpc {
+pub struct ZeroTracerRpc<Provider> {
     /// Database instance.
     db: Arc<dyn Database>,
+    /// Blockchain data provider.
+    provider: Provider,
+    /// Tracer subsystem status.
+    status: Arc<TracerStatus>,
 }
 
-impl ZeroTracerRpc {
+impl<Provider> ZeroTracerRpc<Provider> {
     /// Construct a new ZeroTracerRpc instance.
-    pub fn new(db: Arc<dyn Database>) -> Result<Self, ZeroApiError> {
-        Ok(Self { db })
+    pub fn new(
+        db: Arc<dyn Database>,
+        provider: Provider,
+        status: Arc<TracerStatus>,
+    ) -> Result<Self, ZeroApiError> {
+        Ok(Self {
+            db,
+            provider,
+            status,
+        })
     }
 }
 
 #[async_trait::async_trait]
-impl ZeroTracerRpcApiServer for ZeroTracerRpc {
-    async fn zero_trace_block_by_number(&self, number: BlockNumberOrTag) -> RpcResult<BlockTrace> {
-        let number = if let BlockNumberOrTag::Number(number) = number {
-            number
-        } else {
-            panic!("BlockNumberOrTag::Tag not supported")
-        };
-
+impl<Provider> ZeroTracerRpcApiServer for ZeroTracerRpc<Provider>
+where
+    Provider: BlockReader + BlockIdReader + ChainSpecProvider + Clone + 'static,
+{
+    async fn zero_trace_block_by_number(
+        &self,
+        number: BlockNumberOrTag,
+    ) -> RpcResult<Box<RawValue>> {
+        let number = block_number(&self.provider, number)?;
         let trace = self
             .db
-            .get_block_trace_by_number(number)
+            .get_raw_block_trace_by_number(number)
             .await
             .map_err(ZeroApiError::DatabaseError)?;
         trace.ok_or(ZeroApiError::TraceNotFoundForBlockNumber(number).into())
     }
 
-    async fn zero_trace_block_by_hash(&self, hash: B256) -> RpcResult<BlockTrace> {
+    async fn zero_trace_block_by_hash(&self, hash: B256) -> RpcResult<Box<RawValue>> {
         let trace = self
             .db
-            .get_block_trace_by_hash(hash)
+            .get_raw_block_trace_by_hash(hash)
             .await
             .map_err(ZeroApiError::DatabaseError)?;
         trace.ok_or(ZeroApiError::TraceNotFoundForBlockHash(hash.to_string()).into())
     }
+
+    async fn zero_block_data_by_number(
+        &self,
+        number: BlockNumberOrTag,
+    ) -> RpcResult<OtherBlockData> {
+        let number = block_number(&self.provider, number)?;
+        let block_data = block_data(&self.provider, number)
+            .map_err(|err| ZeroApiError::BlockDataFailed(number, err.to_string()))?;
+        block_data.ok_or(ZeroApiError::BlockNotFound(number).into())
+    }
+
+    async fn zero_status(&self) -> RpcResult<ZeroStatus> {
+        let canonical_tip = self
+            .provider
+            .best_block_number()
+            .map_err(ZeroApiError::ProviderError)?;
+        let latest_traced_block = self.status.latest_traced();
+        Ok(ZeroStatus {
+            latest_traced_block,
+            canonical_tip,
+            lag: latest_traced_block.map(|latest| canonical_tip.saturating_sub(latest)),
+            database: self.status.database().to_string(),
+            database_connected: self.db.ping().await.is_ok(),
+            exex_running: self.status.running(),
+            exex_backfilling: self.status.backfilling(),
+        })
+    }
 }
@@ -0,0 +1,88 @@
+//! Health of the tracer subsystem.
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// State shared between the ExEx, which updates it, and the `zero_status`
+/// RPC method, which reports it.
+#[derive(Debug)]
+pub(crate) struct TracerStatus {
+    /// The configured database backends.
+    database: String,
+    running: AtomicBool,
+    backfilling: AtomicBool,
+    /// The highest traced block number plus one, zero if none is known.
+    latest_traced: AtomicU64,
+}
+
+impl TracerStatus {
+    pub(crate) fn new(database: String) -> Self {
+        Self {
+            database,
+            running: AtomicBool::new(false),
+            backfilling: AtomicBool::new(false),
+            latest_traced: AtomicU64::new(0),
+        }
+    }
+
+    /// Mark the ExEx as running until the returned guard is dropped.
+    pub(crate) fn start(&self) -> RunningGuard<'_> {
+        self.running.store(true, Ordering::Relaxed);
+        RunningGuard(self)
+    }
+
+    pub(crate) fn set_backfilling(&self, backfilling: bool) {
+        self.backfilling.store(backfilling, Ordering::Relaxed);
+    }
+
+    /// Record the number of the latest traced or reverted to block.
+    pub(crate) fn set_latest_traced(&self, number: u64) {
+        self.latest_traced.store(number + 1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn database(&self) -> &str {
+        &self.database
+    }
+
+    pub(crate) fn running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn backfilling(&self) -> bool {
+        self.backfilling.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn latest_traced(&self) -> Option<u64> {
+        self.latest_traced.load(Ordering::Relaxed).checked_sub(1)
+    }
+}
+
+/// Marks the ExEx as stopped when dropped, including when it fails.
+pub(crate) struct RunningGuard<'a>(&'a TracerStatus);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::Relaxed);
+        self.0.backfilling.store(false, Ordering::Relaxed);
+    }
+}
+
+/// The response of `zero_status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ZeroStatus {
+    /// The highest traced block.
+    pub latest_traced_block: Option<u64>,
+    /// The node's canonical tip.
+    pub canonical_tip: u64,
+    /// The number of canonical blocks not yet traced.
+    pub lag: Option<u64>,
+    /// The configured database backends.
+    pub database: String,
+    /// Whether the database is reachable.
+    pub database_connected: bool,
+    /// Whether the ExEx is processing notifications.
+    pub exex_running: bool,
+    /// Whether the ExEx is catching up with the tip, tracing blocks more than
+    /// one block behind it.
+    pub exex_backfilling: bool,
+}
@@ -0,0 +1,157 @@
+//! Block level prover inputs.
+//!
+//! The prover takes the block header fields, the hashes of the preceding
+//! blocks and the block withdrawals alongside the block trace. Withdrawals are
+//! applied by the prover after the last transaction, so they must not also be
+//! credited in the block trace.
+use compat::Compat;
+use evm_arithmetization::proof::{BlockHashes, BlockMetadata};
+use eyre::Result;
+use primitive_types::H256;
+use reth_primitives::{Bloom, Header};
+use reth_provider::{BlockReader, ChainSpecProvider};
+use trace_decoder::{BlockLevelData, OtherBlockData};
+
+use super::post_block::withdrawals;
+
+/// The number of preceding block hashes available to the `BLOCKHASH` opcode.
+const PREV_HASHES: u64 = 256;
+
+/// Build the block level prover inputs of a canonical block.
+///
+/// Returns `None` if the block is not known.
+pub(crate) fn block_data<P>(provider: &P, number: u64) -> Result<Option<OtherBlockData>>
+where
+    P: BlockReader + ChainSpecProvider,
+{
+    let Some(block) = provider.block_by_number(number)? else {
+        return Ok(None);
+    };
+    let checkpoint_state_trie_root = match number.checked_sub(1) {
+        Some(parent) => provider
+            .header_by_number(parent)?
+            .map(|parent| parent.state_root)
+            .unwrap_or_default(),
+        None => block.header.state_root,
+    };
+
+    // the hashes of the 256 preceding blocks, zero padded before genesis
+    let start = number.saturating_sub(PREV_HASHES);
+    let mut prev_hashes = vec![H256::zero(); (PREV_HASHES - (number - start)) as usize];
+    prev_hashes.extend(
+        provider
+            .canonical_hashes_range(start, number)?
+            .into_iter()
+            .map(Compat::compat),
+    );
+
+    Ok(Some(OtherBlockData {
+        b_data: BlockLevelData {
+            b_meta: block_metadata(provider.chain_spec().chain.id(), &block.header),
+            b_hashes: BlockHashes {
+                prev_hashes,
+                cur_hash: block.header.hash_slow().compat(),
+            },
+            withdrawals: withdrawals(block.withdrawals.as_ref())
+                .into_iter()
+                .map(|(address, amount)| (address.compat(), amount.compat()))
+                .collect(),
+        },
+        checkpoint_state_trie_root: checkpoint_state_trie_root.compat(),
+    }))
+}
+
+/// The block metadata of a block header.
+pub(crate) fn block_metadata(chain_id: u64, header: &Header) -> BlockMetadata {
+    BlockMetadata {
+        block_beneficiary: header.beneficiary.compat(),
+        block_timestamp: header.timestamp.into(),
+        block_number: header.number.into(),
+        block_difficulty: header.difficulty.compat(),
+        block_random: header.mix_hash.compat(),
+        block_gaslimit: header.gas_limit.into(),
+        block_chain_id: chain_id.into(),
+        block_base_fee: header.base_fee_per_gas.unwrap_or_default().into(),
+        block_gas_used: header.gas_used.into(),
+        block_blob_gas_used: header.blob_gas_used.unwrap_or_default().into(),
+        block_excess_blob_gas: header.excess_blob_gas.unwrap_or_default().into(),
+        parent_beacon_block_root: header.parent_beacon_block_root.unwrap_or_default().compat(),
+        block_bloom: bloom(&header.logs_bloom),
+    }
+}
+
+/// Split a logs bloom into the eight big endian words used by the prover.
+fn bloom(bloom: &Bloom) -> [primitive_types::U256; 8] {
+    std::array::from_fn(|i| primitive_types::U256::from_big_endian(&bloom[i * 32..(i + 1) * 32]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth_primitives::{Address, Withdrawal, Withdrawals};
+
+    #[test]
+    fn withdrawals_are_block_data_in_wei() {
+        let recipient = Address::repeat_byte(0x11);
+        let withdrawals = withdrawals(Some(&Withdrawals::new(vec![
+            Withdrawal {
+                index: 0,
+                validator_index: 1,
+                address: recipient,
+                amount: 2,
+            },
+            Withdrawal {
+                index: 1,
+                validator_index: 2,
+                address: Address::repeat_byte(0x22),
+                amount: 0,
+            },
+        ])));
+
+        assert_eq!(
+            withdrawals,
+            vec![(recipient, reth_primitives::U256::from(2_000_000_000u64))]
+        );
+    }
+
+    #[test]
+    fn metadata_carries_header_fields() {
+        let mut logs_bloom = Bloom::default();
+        logs_bloom.0[0] = 0x80;
+        logs_bloom.0[255] = 0x01;
+        let header = Header {
+            number: 7,
+            timestamp: 1_700_000_000,
+            gas_limit: 30_000_000,
+            gas_used: 21_000,
+            base_fee_per_gas: Some(7),
+            logs_bloom,
+            ..Default::default()
+        };
+
+        let metadata = block_metadata(1337, &header);
+        assert_eq!(metadata.block_number, 7.into());
+        assert_eq!(metadata.block_chain_id, 1337.into());
+        assert_eq!(metadata.block_base_fee, 7.into());
+        assert_eq!(metadata.block_bloom[0].byte(31), 0x80);
+        assert_eq!(metadata.block_bloom[7], 1.into());
+        assert_eq!(metadata.block_blob_gas_used, 0.into());
+    }
+
+    #[test]
+    fn metadata_carries_blob_gas_fields() {
+        // a Cancun block holding a single type 3 transaction with two blobs
+        let header = Header {
+            number: 8,
+            blob_gas_used: Some(2 * reth_primitives::constants::eip4844::DATA_GAS_PER_BLOB),
+            excess_blob_gas: Some(393_216),
+            parent_beacon_block_root: Some(reth_primitives::B256::repeat_byte(0x42)),
+            ..Default::default()
+        };
+
+        let metadata = block_metadata(1337, &header);
+        assert_eq!(metadata.block_blob_gas_used, 262_144.into());
+        assert_eq!(metadata.block_excess_blob_gas, 393_216.into());
+        assert_eq!(metadata.parent_beacon_block_root, H256::repeat_byte(0x42));
+    }
+}
@@ -0,0 +1,311 @@
+//! Storage proof cache shared across consecutive blocks.
+//!
+//! Hot contracts are accessed in nearly every block, and their storage tries
+//! only change when one of their slots is written. Proof nodes for those
+//! accounts are cached by node hash and reused until a committed block writes
+//! to the account, so that multiproofs only need to walk the slots that were
+//! not proven before.
+//!
+//! Account proofs are always fetched from the state provider. The upper levels
+//! of the state trie change with every block, so cached state trie nodes would
+//! almost never match the current state root.
+use eyre::Result;
+use reth_primitives::{keccak256, Bytes, StorageKey, B256};
+use reth_provider::StateProvider;
+use reth_trie::{MultiProof, Nibbles, StorageMultiProof};
+use revm::primitives::Address;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tracing::debug;
+
+/// A cache of storage proof nodes keyed by node hash.
+#[derive(Debug, Default)]
+pub(crate) struct ProofCache {
+    /// Maximum number of accounts to cache proofs for, zero disables the cache.
+    capacity: usize,
+    /// Proof nodes keyed by node hash, with the number of accounts referencing them.
+    nodes: HashMap<B256, (Bytes, usize)>,
+    /// Cached storage proofs keyed by hashed address.
+    storages: HashMap<B256, CachedStorageProof>,
+    /// Monotonic counter used to evict the least recently used accounts.
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct CachedStorageProof {
+    /// The storage root the proof nodes belong to.
+    root: B256,
+    /// The storage keys covered by the cached proof nodes.
+    slots: HashSet<StorageKey>,
+    /// The proof node hashes keyed by their path in the storage trie.
+    nodes: BTreeMap<Nibbles, B256>,
+    /// The tick at which the proof was last used.
+    last_used: u64,
+}
+
+impl ProofCache {
+    /// Construct a new cache holding proofs for at most `capacity` accounts.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    /// Fetch a multiproof for the given targets, serving storage proof nodes
+    /// from the cache where possible.
+    pub(crate) fn multiproof(
+        &mut self,
+        state: &dyn StateProvider,
+        targets: HashMap<Address, Vec<StorageKey>>,
+    ) -> Result<MultiProof> {
+        if self.capacity == 0 {
+            return Ok(state.multiproof(Default::default(), targets)?);
+        }
+        self.tick += 1;
+
+        // only request proofs for slots which are not already cached
+        let hashed_targets: HashMap<B256, Address> = targets
+            .keys()
+            .map(|address| (keccak256(address), *address))
+            .collect();
+        let reduced_targets = targets
+            .iter()
+            .map(|(address, slots)| {
+                let slots = match self.storages.get(&keccak256(address)) {
+                    Some(cached) => slots
+                        .iter()
+                        .filter(|slot| !cached.slots.contains(*slot))
+                        .copied()
+                        .collect(),
+                    None => slots.clone(),
+                };
+                (*address, slots)
+            })
+            .collect();
+        let mut proof = state.multiproof(Default::default(), reduced_targets)?;
+
+        // merge cached nodes into the fetched proofs, refetching stale entries
+        let mut stale_targets = HashMap::new();
+        let mut hits = 0;
+        for (hashed_addr, storage_proof) in proof.storages.iter_mut() {
+            let Some(cached) = self.storages.get(hashed_addr) else {
+                continue;
+            };
+            if cached.root != storage_proof.root {
+                let address = hashed_targets[hashed_addr];
+                stale_targets.insert(address, targets[&address].clone());
+                continue;
+            }
+            for (path, hash) in &cached.nodes {
+                storage_proof
+                    .subtree
+                    .entry(path.clone())
+                    .or_insert_with(|| self.nodes[hash].0.clone());
+            }
+            hits += 1;
+        }
+
+        if !stale_targets.is_empty() {
+            for address in stale_targets.keys() {
+                self.remove(&keccak256(address));
+            }
+            let refetched = state.multiproof(Default::default(), stale_targets)?;
+            proof.storages.extend(refetched.storages);
+        }
+        debug!(
+            "Proof cache served {} of {} storage proofs",
+            hits,
+            targets.len()
+        );
+
+        for (hashed_addr, storage_proof) in &proof.storages {
+            let address = hashed_targets[hashed_addr];
+            self.insert(*hashed_addr, storage_proof, &targets[&address]);
+        }
+        self.evict();
+
+        Ok(proof)
+    }
+
+    /// Invalidate the cached proofs of accounts whose storage has changed.
+    pub(crate) fn invalidate(&mut self, addresses: impl IntoIterator<Item = Address>) {
+        for address in addresses {
+            self.remove(&keccak256(address));
+        }
+    }
+
+    /// Drop all cached proofs.
+    pub(crate) fn clear(&mut self) {
+        self.nodes.clear();
+        self.storages.clear();
+    }
+
+    fn insert(&mut self, hashed_addr: B256, proof: &StorageMultiProof, slots: &[StorageKey]) {
+        let cached = self
+            .storages
+            .entry(hashed_addr)
+            .or_insert_with(|| CachedStorageProof {
+                root: proof.root,
+                slots: HashSet::new(),
+                nodes: BTreeMap::new(),
+                last_used: 0,
+            });
+        cached.last_used = self.tick;
+        cached.slots.extend(slots.iter().copied());
+
+        for (path, node) in &proof.subtree {
+            if cached.nodes.contains_key(path) {
+                continue;
+            }
+            let hash = keccak256(node);
+            cached.nodes.insert(path.clone(), hash);
+            self.nodes
+                .entry(hash)
+                .or_insert_with(|| (node.clone(), 0))
+                .1 += 1;
+        }
+    }
+
+    fn remove(&mut self, hashed_addr: &B256) {
+        let Some(cached) = self.storages.remove(hashed_addr) else {
+            return;
+        };
+        for hash in cached.nodes.values() {
+            if let Some((_, refs)) = self.nodes.get_mut(hash) {
+                *refs -= 1;
+                if *refs == 0 {
+                    self.nodes.remove(hash);
+                }
+            }
+        }
+    }
+
+    /// Evict the least recently used accounts until the cache is within capacity.
+    fn evict(&mut self) {
+        if self.storages.len() <= self.capacity {
+            return;
+        }
+        let mut by_age: Vec<(u64, B256)> = self
+            .storages
+            .iter()
+            .map(|(hashed_addr, cached)| (cached.last_used, *hashed_addr))
+            .collect();
+        by_age.sort_unstable();
+        let excess = self.storages.len() - self.capacity;
+        for (_, hashed_addr) in by_age.into_iter().take(excess) {
+            self.remove(&hashed_addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use revm::primitives::{Account, AccountInfo, AccountStatus, EvmStorageSlot, U256};
+
+    use super::super::written_accounts;
+
+    fn storage_proof(root: u8, nodes: &[(u8, &[u8])]) -> StorageMultiProof {
+        StorageMultiProof {
+            root: B256::repeat_byte(root),
+            subtree: nodes
+                .iter()
+                .map(|(path, node)| (Nibbles::from_nibbles([*path]), Bytes::copy_from_slice(node)))
+                .collect(),
+        }
+    }
+
+    fn account(storage: impl IntoIterator<Item = (U256, EvmStorageSlot)>) -> Account {
+        Account {
+            info: AccountInfo::default(),
+            storage: storage.into_iter().collect(),
+            status: AccountStatus::Touched,
+        }
+    }
+
+    #[test]
+    fn shared_nodes_are_reference_counted() {
+        let (hot, cold) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let mut cache = ProofCache::new(2);
+        cache.insert(
+            keccak256(hot),
+            &storage_proof(1, &[(0, b"shared"), (1, b"hot")]),
+            &[StorageKey::with_last_byte(1)],
+        );
+        cache.insert(
+            keccak256(cold),
+            &storage_proof(2, &[(0, b"shared")]),
+            &[StorageKey::with_last_byte(2)],
+        );
+        assert_eq!(cache.nodes[&keccak256(b"shared")].1, 2);
+
+        cache.invalidate([hot]);
+        assert!(!cache.storages.contains_key(&keccak256(hot)));
+        assert!(!cache.nodes.contains_key(&keccak256(b"hot")));
+        assert_eq!(cache.nodes[&keccak256(b"shared")].1, 1);
+    }
+
+    #[test]
+    fn accounts_written_in_a_block_are_invalidated() {
+        let written = Address::repeat_byte(1);
+        let read = Address::repeat_byte(2);
+        let mut cache = ProofCache::new(2);
+        for (byte, address) in [(1, written), (2, read)] {
+            cache.insert(
+                keccak256(address),
+                &storage_proof(byte, &[(byte, &[byte])]),
+                &[StorageKey::with_last_byte(byte)],
+            );
+        }
+
+        // the first transaction only reads both accounts, the second writes
+        // a slot of the first one
+        let tx_traces = vec![
+            HashMap::from([
+                (
+                    written,
+                    account([(U256::from(1), EvmStorageSlot::new(U256::from(1)))]),
+                ),
+                (
+                    read,
+                    account([(U256::from(2), EvmStorageSlot::new(U256::from(2)))]),
+                ),
+            ]),
+            HashMap::from([(
+                written,
+                account([(
+                    U256::from(1),
+                    EvmStorageSlot::new_changed(U256::from(1), U256::from(3)),
+                )]),
+            )]),
+        ];
+        cache.invalidate(written_accounts(&tx_traces));
+
+        assert!(!cache.storages.contains_key(&keccak256(written)));
+        assert!(!cache.nodes.contains_key(&keccak256([1])));
+        assert!(cache.storages.contains_key(&keccak256(read)));
+        assert!(cache.nodes.contains_key(&keccak256([2])));
+    }
+
+    #[test]
+    fn least_recently_used_accounts_are_evicted() {
+        let mut cache = ProofCache::new(1);
+        for byte in 1..=2u8 {
+            cache.tick += 1;
+            cache.insert(
+                keccak256(Address::repeat_byte(byte)),
+                &storage_proof(byte, &[(byte, &[byte])]),
+                &[StorageKey::with_last_byte(byte)],
+            );
+        }
+        cache.evict();
+
+        assert!(!cache
+            .storages
+            .contains_key(&keccak256(Address::repeat_byte(1))));
+        assert!(cache
+            .storages
+            .contains_key(&keccak256(Address::repeat_byte(2))));
+        assert_eq!(cache.nodes.len(), 1);
+    }
+}
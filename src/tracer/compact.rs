@@ -0,0 +1,366 @@
+//! Encoder for the combined (compact) trie pre-image format.
+//!
+//! The compact format is a stack machine instruction stream: child nodes are
+//! emitted before their parent and every instruction argument is CBOR encoded.
+//! Storage tries and contract code are embedded directly under the account
+//! leaf that owns them, so a single stream describes the whole state witness.
+use compat::Compat;
+use eyre::{eyre, Result};
+use mpt_trie::{
+    nibbles::Nibbles,
+    partial_trie::{HashedPartialTrie, Node},
+};
+use reth_primitives::{constants::EMPTY_ROOT_HASH, KECCAK_EMPTY};
+use std::collections::HashMap;
+
+use super::trie::{nibbles_to_vec, TrieAccount};
+
+/// The compact format version understood by trace_decoder.
+const HEADER_VERSION: u8 = 1;
+
+/// Account leaf flags.
+const FLAG_CODE: u64 = 0b0001;
+const FLAG_STORAGE: u64 = 0b0010;
+const FLAG_NONCE: u64 = 0b0100;
+const FLAG_BALANCE: u64 = 0b1000;
+
+/// CBOR major types used by the encoding.
+const CBOR_UINT: u8 = 0;
+const CBOR_BYTES: u8 = 2;
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+enum Opcode {
+    Leaf = 0x00,
+    Extension = 0x01,
+    Branch = 0x02,
+    Hash = 0x03,
+    Code = 0x04,
+    AccountLeaf = 0x05,
+    EmptyRoot = 0x06,
+}
+
+/// Encode the state trie, its storage tries and the contract code into the
+/// compact pre-image format.
+pub(crate) fn encode_combined(
+    state: &HashedPartialTrie,
+    storage: &HashMap<primitive_types::H256, HashedPartialTrie>,
+    code_db: &HashMap<primitive_types::H256, Vec<u8>>,
+) -> Result<Vec<u8>> {
+    let mut encoder = CompactEncoder {
+        out: vec![HEADER_VERSION],
+        storage,
+        code_db,
+    };
+    encoder.write_state_node(state, &mut Vec::new())?;
+    Ok(encoder.out)
+}
+
+struct CompactEncoder<'a> {
+    out: Vec<u8>,
+    storage: &'a HashMap<primitive_types::H256, HashedPartialTrie>,
+    code_db: &'a HashMap<primitive_types::H256, Vec<u8>>,
+}
+
+impl CompactEncoder<'_> {
+    /// Write a state trie node, tracking the nibble path so that account
+    /// leaves can be matched with their storage tries.
+    fn write_state_node(&mut self, node: &HashedPartialTrie, path: &mut Vec<u8>) -> Result<()> {
+        match &**node {
+            Node::Empty => self.write_op(Opcode::EmptyRoot),
+            Node::Hash(hash) => {
+                self.write_op(Opcode::Hash);
+                self.write_bytes(hash.as_bytes());
+            }
+            Node::Branch { children, .. } => {
+                let mut mask = 0u64;
+                for (nibble, child) in children.iter().enumerate() {
+                    let child: &HashedPartialTrie = child.as_ref();
+                    if matches!(&**child, Node::Empty) {
+                        continue;
+                    }
+                    mask |= 1 << nibble;
+                    path.push(nibble as u8);
+                    self.write_state_node(child, path)?;
+                    path.pop();
+                }
+                self.write_op(Opcode::Branch);
+                self.write_uint(mask);
+            }
+            Node::Extension { nibbles, child } => {
+                let depth = path.len();
+                path.extend(nibbles_to_vec(nibbles));
+                self.write_state_node(child.as_ref(), path)?;
+                path.truncate(depth);
+                self.write_op(Opcode::Extension);
+                self.write_bytes(&nibbles.to_hex_prefix_encoding(false));
+            }
+            Node::Leaf { nibbles, value } => {
+                let mut full_path = path.clone();
+                full_path.extend(nibbles_to_vec(nibbles));
+                self.write_account_leaf(&full_path, nibbles, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a storage trie node. Storage leaves carry their value as is.
+    fn write_storage_node(&mut self, node: &HashedPartialTrie) {
+        match &**node {
+            Node::Empty => self.write_op(Opcode::EmptyRoot),
+            Node::Hash(hash) => {
+                self.write_op(Opcode::Hash);
+                self.write_bytes(hash.as_bytes());
+            }
+            Node::Branch { children, .. } => {
+                let mut mask = 0u64;
+                for (nibble, child) in children.iter().enumerate() {
+                    let child: &HashedPartialTrie = child.as_ref();
+                    if matches!(&**child, Node::Empty) {
+                        continue;
+                    }
+                    mask |= 1 << nibble;
+                    self.write_storage_node(child);
+                }
+                self.write_op(Opcode::Branch);
+                self.write_uint(mask);
+            }
+            Node::Extension { nibbles, child } => {
+                self.write_storage_node(child.as_ref());
+                self.write_op(Opcode::Extension);
+                self.write_bytes(&nibbles.to_hex_prefix_encoding(false));
+            }
+            Node::Leaf { nibbles, value } => {
+                self.write_op(Opcode::Leaf);
+                self.write_bytes(&nibbles.to_hex_prefix_encoding(true));
+                self.write_bytes(value);
+            }
+        }
+    }
+
+    /// Write an account leaf, preceded by its code and storage trie.
+    fn write_account_leaf(
+        &mut self,
+        full_path: &[u8],
+        nibbles: &Nibbles,
+        value: &[u8],
+    ) -> Result<()> {
+        let account = TrieAccount::decode(value)?;
+        let hashed_address = primitive_types::H256::from_slice(&pack_nibbles(full_path)?);
+        let mut flags = 0;
+
+        if account.code_hash != KECCAK_EMPTY {
+            flags |= FLAG_CODE;
+            match self.code_db.get(&account.code_hash.compat()) {
+                Some(code) => {
+                    self.write_op(Opcode::Code);
+                    self.write_bytes(code);
+                }
+                None => {
+                    self.write_op(Opcode::Hash);
+                    self.write_bytes(account.code_hash.as_slice());
+                }
+            }
+        }
+
+        if account.storage_root != EMPTY_ROOT_HASH {
+            flags |= FLAG_STORAGE;
+            match self.storage.get(&hashed_address) {
+                Some(storage) if !matches!(&**storage, Node::Empty) => {
+                    self.write_storage_node(storage)
+                }
+                _ => {
+                    self.write_op(Opcode::Hash);
+                    self.write_bytes(account.storage_root.as_slice());
+                }
+            }
+        }
+
+        if account.nonce != 0 {
+            flags |= FLAG_NONCE;
+        }
+        if !account.balance.is_zero() {
+            flags |= FLAG_BALANCE;
+        }
+
+        self.write_op(Opcode::AccountLeaf);
+        self.write_bytes(&nibbles.to_hex_prefix_encoding(true));
+        self.write_uint(flags);
+        if flags & FLAG_NONCE != 0 {
+            self.write_uint(account.nonce);
+        }
+        if flags & FLAG_BALANCE != 0 {
+            let balance = account.balance.to_be_bytes_trimmed_vec();
+            self.write_bytes(&balance);
+        }
+        Ok(())
+    }
+
+    fn write_op(&mut self, op: Opcode) {
+        self.out.push(op as u8);
+    }
+
+    fn write_uint(&mut self, value: u64) {
+        self.write_cbor_header(CBOR_UINT, value);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_cbor_header(CBOR_BYTES, bytes.len() as u64);
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn write_cbor_header(&mut self, major: u8, value: u64) {
+        let major = major << 5;
+        match value {
+            0..=23 => self.out.push(major | value as u8),
+            24..=0xff => self.out.extend([major | 24, value as u8]),
+            0x100..=0xffff => {
+                self.out.push(major | 25);
+                self.out.extend((value as u16).to_be_bytes());
+            }
+            0x10000..=0xffff_ffff => {
+                self.out.push(major | 26);
+                self.out.extend((value as u32).to_be_bytes());
+            }
+            _ => {
+                self.out.push(major | 27);
+                self.out.extend(value.to_be_bytes());
+            }
+        }
+    }
+}
+
+fn pack_nibbles(nibbles: &[u8]) -> Result<Vec<u8>> {
+    if nibbles.len() % 2 != 0 {
+        return Err(eyre!("account leaf path has an odd number of nibbles"));
+    }
+    Ok(nibbles
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpt_trie::{partial_trie::PartialTrie, trie_subsets::create_trie_subset};
+    use reth_primitives::{keccak256, Address, B256, U256};
+    use trace_decoder::{
+        BlockLevelData, BlockTrace, BlockTraceTriePreImages, CombinedPreImages, OtherBlockData,
+    };
+
+    use super::super::{separate_pre_images, trie::trie_from_entries};
+
+    const CODE: &[u8] = &[0x60, 0x01, 0x60, 0x00, 0x55, 0x00];
+
+    /// A state with a contract holding storage and code, and a plain account
+    /// which is blinded to a hash node.
+    fn witness() -> (
+        HashedPartialTrie,
+        HashMap<primitive_types::H256, HashedPartialTrie>,
+        HashMap<primitive_types::H256, Vec<u8>>,
+    ) {
+        let contract = keccak256(Address::repeat_byte(0x11));
+        let plain = keccak256(Address::repeat_byte(0x22));
+        let storage = trie_from_entries([(1u64, 7u64), (2, 9), (3, 11)].map(|(slot, value)| {
+            (
+                keccak256(B256::from(U256::from(slot))).compat(),
+                alloy_rlp::encode(U256::from(value)),
+            )
+        }));
+
+        let accounts = [
+            (
+                contract,
+                TrieAccount {
+                    nonce: 1,
+                    balance: U256::from(100),
+                    storage_root: storage.hash().compat(),
+                    code_hash: keccak256(CODE),
+                },
+            ),
+            (
+                plain,
+                TrieAccount {
+                    nonce: 0,
+                    balance: U256::from(5),
+                    storage_root: EMPTY_ROOT_HASH,
+                    code_hash: KECCAK_EMPTY,
+                },
+            ),
+        ];
+        let state = trie_from_entries(
+            accounts
+                .iter()
+                .map(|(hashed_address, account)| (hashed_address.compat(), account.encode())),
+        );
+        let state = create_trie_subset(&state, [Nibbles::from_h256_be(contract.compat())])
+            .expect("contract is revealed");
+
+        (
+            state,
+            HashMap::from([(contract.compat(), storage)]),
+            HashMap::from([(keccak256(CODE).compat(), CODE.to_vec())]),
+        )
+    }
+
+    /// Decode a block trace without transactions and return the state root and
+    /// the non-empty storage roots of the prover input.
+    fn decoded_roots(
+        trie_pre_images: BlockTraceTriePreImages,
+        code_db: &HashMap<primitive_types::H256, Vec<u8>>,
+        state_root: primitive_types::H256,
+    ) -> (
+        primitive_types::H256,
+        Vec<(primitive_types::H256, primitive_types::H256)>,
+    ) {
+        let block_trace = BlockTrace {
+            trie_pre_images,
+            code_db: Some(code_db.clone()),
+            txn_info: vec![],
+        };
+        let other_data = OtherBlockData {
+            b_data: BlockLevelData {
+                b_meta: Default::default(),
+                b_hashes: Default::default(),
+                withdrawals: vec![],
+            },
+            checkpoint_state_trie_root: state_root,
+        };
+        let inputs =
+            trace_decoder::entrypoint(block_trace, other_data, 1).expect("block trace decodes");
+        let tries = &inputs[0].tries;
+
+        let mut storage_roots: Vec<_> = tries
+            .storage_tries
+            .iter()
+            .map(|(hashed_address, trie)| (*hashed_address, trie.hash()))
+            .filter(|(_, root)| *root != EMPTY_ROOT_HASH.compat())
+            .collect();
+        storage_roots.sort();
+        (tries.state_trie.hash(), storage_roots)
+    }
+
+    #[test]
+    fn combined_pre_images_decode_like_separate_pre_images() {
+        let (state, storage, code_db) = witness();
+        let state_root = state.hash();
+
+        let combined = BlockTraceTriePreImages::Combined(CombinedPreImages {
+            compact: encode_combined(&state, &storage, &code_db).expect("witness encodes"),
+        });
+        let separate = separate_pre_images(state, storage.clone());
+
+        let combined = decoded_roots(combined, &code_db, state_root);
+        let separate = decoded_roots(separate, &code_db, state_root);
+        assert_eq!(combined, separate);
+        assert_eq!(combined.0, state_root);
+        assert_eq!(
+            combined.1,
+            storage
+                .iter()
+                .map(|(hashed_address, trie)| (*hashed_address, trie.hash()))
+                .collect::<Vec<_>>()
+        );
+    }
+}
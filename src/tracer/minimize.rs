@@ -0,0 +1,225 @@
+//! Witness minimization.
+//!
+//! The prover only traverses the trie paths of the accounts and storage slots
+//! which appear in the transaction traces. Every other subtree, such as slots
+//! which were only loaded during execution or proof nodes served from the
+//! proof cache for other slots, is replaced by its hash without changing the
+//! trie roots, which directly reduces proving cost. Storage tries of accounts
+//! whose storage is not traversed are collapsed to their root hash, or dropped
+//! if empty.
+use compat::Compat;
+use eyre::Result;
+use mpt_trie::{
+    nibbles::Nibbles,
+    partial_trie::{HashedPartialTrie, Node, PartialTrie},
+    trie_subsets::create_trie_subset,
+};
+use primitive_types::H256;
+use reth_primitives::{constants::EMPTY_ROOT_HASH, keccak256};
+use std::collections::{HashMap, HashSet};
+use trace_decoder::TxnInfo;
+use tracing::info;
+
+use super::trie::trie_nodes;
+
+/// The hashed addresses of the accounts traversed by the prover, with the
+/// hashed storage slots read or written at.
+pub(crate) fn traversed_paths(txn_infos: &[TxnInfo]) -> HashMap<H256, HashSet<H256>> {
+    let mut paths: HashMap<H256, HashSet<H256>> = HashMap::new();
+    for (address, trace) in txn_infos.iter().flat_map(|txn_info| &txn_info.traces) {
+        let slots = paths
+            .entry(keccak256(address.as_bytes()).compat())
+            .or_default();
+        let read = trace.storage_read.iter().flatten();
+        let written = trace
+            .storage_written
+            .iter()
+            .flat_map(|written| written.keys());
+        slots.extend(
+            read.chain(written)
+                .map(|slot| keccak256(slot.as_bytes()).compat()),
+        );
+    }
+    paths
+}
+
+/// Prune the state and storage witnesses down to the traversed paths.
+pub(crate) fn minimize_witness(
+    state_trie: HashedPartialTrie,
+    storage_tries: HashMap<H256, HashedPartialTrie>,
+    paths: &HashMap<H256, HashSet<H256>>,
+) -> Result<(HashedPartialTrie, HashMap<H256, HashedPartialTrie>)> {
+    let size_before =
+        witness_size(&state_trie) + storage_tries.values().map(witness_size).sum::<usize>();
+
+    let state_trie = create_trie_subset(
+        &state_trie,
+        paths
+            .keys()
+            .map(|hashed_addr| Nibbles::from_h256_be(*hashed_addr)),
+    )?;
+
+    let mut minimized = HashMap::new();
+    for (hashed_addr, trie) in storage_tries {
+        let slots = paths.get(&hashed_addr).filter(|slots| !slots.is_empty());
+        if let Some(trie) = minimize_storage_trie(trie, slots)? {
+            minimized.insert(hashed_addr, trie);
+        }
+    }
+
+    let size_after =
+        witness_size(&state_trie) + minimized.values().map(witness_size).sum::<usize>();
+    metrics::histogram!("zeth_witness_bytes", "stage" => "traced").record(size_before as f64);
+    metrics::histogram!("zeth_witness_bytes", "stage" => "minimized").record(size_after as f64);
+    info!(
+        "Minimized witness from {} to {} bytes",
+        size_before, size_after
+    );
+
+    Ok((state_trie, minimized))
+}
+
+/// Keep only the paths of the traversed slots of a storage trie. A storage
+/// trie without traversed slots is collapsed to its root hash, or dropped if
+/// it is empty.
+fn minimize_storage_trie(
+    trie: HashedPartialTrie,
+    slots: Option<&HashSet<H256>>,
+) -> Result<Option<HashedPartialTrie>> {
+    if let Some(slots) = slots {
+        return Ok(Some(create_trie_subset(
+            &trie,
+            slots.iter().map(|slot| Nibbles::from_h256_be(*slot)),
+        )?));
+    }
+
+    let root = trie.hash();
+    if root == EMPTY_ROOT_HASH.compat() {
+        Ok(None)
+    } else {
+        Ok(Some(HashedPartialTrie::new(Node::Hash(root))))
+    }
+}
+
+/// The total length of the RLP encoded trie nodes revealed by a witness.
+pub(crate) fn witness_size(trie: &HashedPartialTrie) -> usize {
+    trie_nodes(trie).iter().map(Vec::len).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth_primitives::{Address, B256, U256};
+    use trace_decoder::{TxnMeta, TxnTrace};
+
+    use super::super::trie::{trie_from_entries, TrieAccount};
+
+    fn slot_key(slot: u64) -> H256 {
+        keccak256(B256::from(U256::from(slot))).compat()
+    }
+
+    fn account(storage_root: B256) -> Vec<u8> {
+        TrieAccount {
+            nonce: 1,
+            balance: U256::from(1),
+            storage_root,
+            code_hash: reth_primitives::KECCAK_EMPTY,
+        }
+        .encode()
+    }
+
+    fn trace(
+        storage_read: Option<Vec<H256>>,
+        storage_written: Option<HashMap<H256, primitive_types::U256>>,
+    ) -> TxnTrace {
+        TxnTrace {
+            balance: Some(1.into()),
+            nonce: Some(1.into()),
+            storage_read,
+            storage_written,
+            code_usage: None,
+            self_destructed: None,
+        }
+    }
+
+    fn txn_info(traces: impl IntoIterator<Item = (Address, TxnTrace)>) -> TxnInfo {
+        TxnInfo {
+            meta: TxnMeta {
+                byte_code: vec![],
+                new_receipt_trie_node_byte: vec![],
+                gas_used: 0,
+            },
+            traces: traces
+                .into_iter()
+                .map(|(address, trace)| (address.compat(), trace))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn minimized_witness_is_smaller_and_keeps_roots() {
+        let contract = Address::repeat_byte(0x11);
+        let sender = Address::repeat_byte(0x22);
+        let idle = Address::repeat_byte(0x33);
+
+        // a fully revealed storage trie, as served by a warm proof cache, and
+        // a state trie with untouched neighbouring accounts
+        let storage = trie_from_entries(
+            (0..64).map(|slot| (slot_key(slot), alloy_rlp::encode(U256::from(slot + 1)))),
+        );
+        let state = trie_from_entries(
+            (0x40..0x80u8)
+                .map(|byte| Address::repeat_byte(byte))
+                .chain([sender, idle])
+                .map(|address| (keccak256(address).compat(), account(EMPTY_ROOT_HASH)))
+                .chain([(
+                    keccak256(contract).compat(),
+                    account(storage.hash().compat()),
+                )]),
+        );
+        let storage_tries = HashMap::from([
+            (keccak256(contract).compat(), storage.clone()),
+            (keccak256(sender).compat(), HashedPartialTrie::default()),
+            (keccak256(idle).compat(), HashedPartialTrie::default()),
+        ]);
+
+        // the sender writes slot 1 of the contract and reads slot 2
+        let txn_infos = [txn_info([
+            (
+                contract,
+                trace(
+                    Some(vec![B256::from(U256::from(2)).compat()]),
+                    Some(HashMap::from([(
+                        B256::from(U256::from(1)).compat(),
+                        U256::from(42).compat(),
+                    )])),
+                ),
+            ),
+            (sender, trace(None, None)),
+        ])];
+        let paths = traversed_paths(&txn_infos);
+
+        let size_before =
+            witness_size(&state) + storage_tries.values().map(witness_size).sum::<usize>();
+        let (minimized_state, minimized_storage) =
+            minimize_witness(state.clone(), storage_tries, &paths).expect("witness minimizes");
+        let size_after = witness_size(&minimized_state)
+            + minimized_storage.values().map(witness_size).sum::<usize>();
+
+        assert!(size_after < size_before);
+        assert_eq!(minimized_state.hash(), state.hash());
+        assert_eq!(
+            minimized_storage.keys().collect::<Vec<_>>(),
+            vec![&keccak256(contract).compat()]
+        );
+
+        // the traced write still produces the same post-state storage root
+        let key = Nibbles::from_h256_be(slot_key(1));
+        let value = alloy_rlp::encode(U256::from(42));
+        let mut full = storage;
+        full.insert(key, value.clone()).expect("slot is revealed");
+        let mut minimized = minimized_storage[&keccak256(contract).compat()].clone();
+        minimized.insert(key, value).expect("slot is revealed");
+        assert_eq!(minimized.hash(), full.hash());
+    }
+}
@@ -0,0 +1,754 @@
+use alloy_rlp::{BufMut, Encodable};
+use compat::Compat;
+use eyre::{eyre, Result};
+use mpt_trie::{builder::PartialTrieBuilder, partial_trie::HashedPartialTrie};
+use reth_primitives::{
+    keccak256, proofs::calculate_receipt_root, ChainSpec, Receipt, SealedBlockWithSenders,
+    StorageKey, TransactionSigned, TxType, B256,
+};
+use reth_provider::{HeaderProvider, StateProvider, StateProviderFactory};
+use reth_revm::primitives::state::EvmState;
+use reth_trie::StorageMultiProof;
+use revm::{
+    db::ExecutionTrace,
+    primitives::{Account, Address, U256},
+};
+use std::collections::{HashMap, HashSet};
+use trace_decoder::{
+    BlockTrace, BlockTraceTriePreImages, CombinedPreImages, ContractCodeUsage,
+    SeparateStorageTriesPreImage, SeparateTriePreImage, SeparateTriePreImages, TxnInfo, TxnMeta,
+    TxnTrace,
+};
+
+use super::error::TracerError;
+
+mod block_data;
+mod cache;
+mod compact;
+mod minimize;
+mod post_block;
+mod retrace;
+mod system;
+mod trie;
+mod verify;
+
+pub(crate) use block_data::block_data;
+pub(crate) use cache::ProofCache;
+pub(crate) use retrace::retrace_block;
+pub(crate) use trie::{trie_from_nodes, trie_nodes};
+pub(crate) use verify::verify_block_trace;
+
+/// The trie pre-image format emitted in block traces.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum PreImageFormat {
+    /// A separate state trie and one trie per accessed storage.
+    #[default]
+    Separate,
+    /// A single compact encoding with storage tries embedded in the state trie.
+    Combined,
+}
+
+/// Configuration for the zero tracer.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TracerConfig {
+    /// The trie pre-image format emitted in block traces.
+    pub pre_image_format: PreImageFormat,
+    /// Prune witness subtrees that the prover does not traverse.
+    pub minimize_witness: bool,
+    /// Number of accounts to cache storage proofs for across blocks.
+    pub proof_cache_size: usize,
+    /// Re-execute every traced block against its witness in the background.
+    pub verify_witness: bool,
+}
+
+/// What a block is traced against: the parent state and chain, the tracer
+/// configuration and the proof cache carried across blocks.
+pub(crate) struct TraceContext<'a, P> {
+    pub provider: &'a P,
+    pub chain_spec: &'a ChainSpec,
+    pub config: &'a TracerConfig,
+    pub cache: &'a mut ProofCache,
+}
+
+pub(crate) fn trace_block<P: StateProviderFactory + HeaderProvider>(
+    ctx: TraceContext<'_, P>,
+    block: SealedBlockWithSenders,
+    receipts: Vec<Option<Receipt>>,
+    trace: ExecutionTrace,
+    tx_traces: Vec<HashMap<Address, Account>>,
+) -> Result<BlockTrace> {
+    let TraceContext {
+        provider,
+        chain_spec,
+        config,
+        cache,
+    } = ctx;
+    let receipts = check_receipts(&block, receipts, &tx_traces)?;
+    let db = provider.state_by_block_hash(block.parent_hash)?;
+    let mut code_db = HashMap::new();
+    let mut txn_infos = vec![];
+    let mut cum_gas = 0;
+    let written_accounts = written_accounts(&tx_traces);
+    let mut state_access = trace.accounts;
+
+    if let Some(system_call_info) = system::system_call_trace(
+        chain_spec,
+        &block.header,
+        db.as_ref(),
+        &mut code_db,
+        &mut state_access,
+    )? {
+        txn_infos.push(system_call_info);
+    }
+
+    let total_difficulty = provider
+        .header_td_by_number(block.number)?
+        .ok_or_else(|| eyre!("total difficulty of block {} not found", block.number))?;
+    let withdrawals = post_block::withdrawals(block.withdrawals.as_ref());
+    for (address, _) in &withdrawals {
+        state_access.entry(*address).or_default();
+    }
+    let mut post_block_state = post_block::PostBlockState::default();
+    post_block_state.credit_block_rewards(
+        chain_spec,
+        &block.header,
+        &block.ommers,
+        total_difficulty,
+    );
+
+    let cancun = chain_spec.is_cancun_active_at_timestamp(block.timestamp);
+    for ((tx, tx_trace), receipt) in block
+        .into_transactions_ecrecovered()
+        .zip(tx_traces.iter())
+        .zip(receipts.into_iter())
+    {
+        txn_infos.push(trace_transaction(
+            &tx,
+            receipt,
+            tx_trace,
+            &mut code_db,
+            &mut cum_gas,
+            cancun,
+        ));
+    }
+
+    if let Some(post_block_info) =
+        post_block_state.into_trace(db.as_ref(), &tx_traces, &mut state_access)?
+    {
+        txn_infos.push(post_block_info);
+    }
+
+    let (state_trie, storage_tries) = match config.minimize_witness {
+        true => {
+            let mut paths = minimize::traversed_paths(&txn_infos);
+            for (address, _) in &withdrawals {
+                paths.entry(keccak256(address).compat()).or_default();
+            }
+            let (state_trie, storage_tries) = state_witness(db, cache, state_access)?;
+            minimize::minimize_witness(state_trie, storage_tries, &paths)?
+        }
+        false => state_witness(db, cache, state_access)?,
+    };
+    cache.invalidate(written_accounts);
+    let trie_pre_images = match config.pre_image_format {
+        PreImageFormat::Separate => separate_pre_images(state_trie, storage_tries),
+        PreImageFormat::Combined => BlockTraceTriePreImages::Combined(CombinedPreImages {
+            compact: compact::encode_combined(&state_trie, &storage_tries, &code_db)?,
+        }),
+    };
+
+    Ok(BlockTrace {
+        trie_pre_images,
+        code_db: Some(code_db),
+        txn_info: txn_infos,
+    })
+}
+
+/// Check that the receipts and transaction traces align with the block's
+/// transactions, that the cumulative gas used adds up to the header's gas
+/// used and that the receipts hash to the header's receipts root.
+fn check_receipts(
+    block: &SealedBlockWithSenders,
+    receipts: Vec<Option<Receipt>>,
+    tx_traces: &[HashMap<Address, Account>],
+) -> Result<Vec<Receipt>, TracerError> {
+    let transactions = block.body.len();
+    if receipts.len() != transactions {
+        return Err(TracerError::ReceiptCountMismatch {
+            transactions,
+            receipts: receipts.len(),
+        });
+    }
+    if tx_traces.len() != transactions {
+        return Err(TracerError::TxTraceCountMismatch {
+            transactions,
+            traces: tx_traces.len(),
+        });
+    }
+
+    let mut previous = 0;
+    let receipts = receipts
+        .into_iter()
+        .enumerate()
+        .map(|(index, receipt)| {
+            let receipt = receipt.ok_or(TracerError::MissingReceipt(index))?;
+            if receipt.cumulative_gas_used < previous {
+                return Err(TracerError::CumulativeGasDecreased {
+                    index,
+                    previous,
+                    current: receipt.cumulative_gas_used,
+                });
+            }
+            previous = receipt.cumulative_gas_used;
+            Ok(receipt)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if previous != block.gas_used {
+        return Err(TracerError::GasUsedMismatch {
+            header: block.gas_used,
+            receipts: previous,
+        });
+    }
+
+    let receipts_root = calculate_receipt_root(
+        &receipts
+            .iter()
+            .cloned()
+            .map(Receipt::with_bloom)
+            .collect::<Vec<_>>(),
+    );
+    if receipts_root != block.receipts_root {
+        return Err(TracerError::ReceiptsRootMismatch {
+            header: block.receipts_root,
+            receipts: receipts_root,
+        });
+    }
+    Ok(receipts)
+}
+
+/// The transaction as committed to in the transactions trie.
+///
+/// Typed transactions are encoded as `type || rlp(payload)`. The network form
+/// of an EIP-4844 transaction additionally wraps the blob sidecar, which is
+/// never part of a block, so type 3 transactions are encoded like any other
+/// typed transaction.
+fn transaction_bytes(tx: &TransactionSigned) -> Vec<u8> {
+    match tx.tx_type() {
+        TxType::Legacy => alloy_rlp::encode(tx),
+        // including EIP-4844, whose block form never carries the sidecar
+        _ => tx.envelope_encoded().to_vec(),
+    }
+}
+
+/// The receipt trie node value as expected by the prover.
+///
+/// Legacy receipts are encoded as a plain RLP list, typed receipts, including
+/// those of EIP-4844 transactions, as `type || rlp(receipt)` wrapped in an RLP
+/// string.
+fn receipt_bytes(receipt: Receipt) -> Vec<u8> {
+    let mut buf = vec![];
+    receipt.with_bloom().encode(&mut buf as &mut dyn BufMut);
+    buf
+}
+
+fn trace_transaction(
+    tx: &TransactionSigned,
+    receipt: Receipt,
+    state: &EvmState,
+    code_db: &mut HashMap<primitive_types::H256, Vec<u8>>,
+    cum_gas: &mut u64,
+    cancun: bool,
+) -> TxnInfo {
+    let meta = TxnMeta {
+        byte_code: transaction_bytes(tx),
+        gas_used: {
+            let previous_cum_gas = std::mem::replace(cum_gas, receipt.cumulative_gas_used);
+            receipt.cumulative_gas_used - previous_cum_gas
+        },
+        new_receipt_trie_node_byte: receipt_bytes(receipt),
+    };
+
+    let traces = state
+        .iter()
+        .map(|(address, state)| {
+            // under EIP-6780 an account is only destroyed in the transaction
+            // that created it, so none of its state outlives the transaction
+            // and it is left empty. Before Cancun an existing account can be
+            // destroyed, and the prover still reads its accessed slots and code.
+            if state.is_selfdestructed() {
+                let existed = !cancun && !state.is_created();
+                let storage_read: Vec<_> = state
+                    .storage
+                    .keys()
+                    .filter(|_| existed)
+                    .map(|key| Into::<B256>::into(*key).compat())
+                    .collect();
+                let trace = TxnTrace {
+                    balance: Some(primitive_types::U256::zero()),
+                    nonce: Some(primitive_types::U256::zero()),
+                    storage_read: Some(storage_read).filter(|x| !x.is_empty()),
+                    storage_written: None,
+                    code_usage: code_usage(state, code_db).filter(|_| existed),
+                    self_destructed: Some(true),
+                };
+                return ((*address).compat(), trace);
+            }
+
+            let mut storage_read = vec![];
+            let mut storage_written: HashMap<primitive_types::H256, _> = HashMap::new();
+
+            for (key, value) in state.storage.clone().into_iter() {
+                // a created account starts with empty storage, so reads and
+                // zero writes do not touch the pre-state storage trie
+                if state.is_created() && value.present_value.is_zero() {
+                    continue;
+                }
+                match value.is_changed() {
+                    true => {
+                        storage_written.insert(
+                            Into::<B256>::into(key).compat(),
+                            value.present_value.compat(),
+                        );
+                    }
+                    false => {
+                        storage_read.push(Into::<B256>::into(key).compat());
+                    }
+                }
+            }
+
+            let trace = TxnTrace {
+                balance: Some(state.info.balance.compat()).filter(|_| state.is_touched()),
+                nonce: Some(state.info.nonce.into()).filter(|_| state.is_touched()),
+                storage_read: Some(storage_read).filter(|x| !x.is_empty()),
+                storage_written: Some(storage_written).filter(|x| !x.is_empty()),
+                code_usage: code_usage(state, code_db),
+                self_destructed: None,
+            };
+
+            ((*address).compat(), trace)
+        })
+        .collect();
+
+    TxnInfo { meta, traces }
+}
+
+/// The code read or deployed by an account in a transaction, recording it in
+/// `code_db`.
+fn code_usage(
+    state: &Account,
+    code_db: &mut HashMap<primitive_types::H256, Vec<u8>>,
+) -> Option<ContractCodeUsage> {
+    if state.info.is_empty_code_hash() || state.info.code_hash() == B256::ZERO {
+        return None;
+    }
+    state.info.code.clone().map(|code| {
+        code_db.insert(
+            state.info.code_hash.compat(),
+            code.original_bytes().to_vec(),
+        );
+        match state.is_created() {
+            true => ContractCodeUsage::Write(code.original_bytes().to_vec()),
+            false => ContractCodeUsage::Read(state.info.code_hash.compat()),
+        }
+    })
+}
+
+/// Collect the accounts whose storage was changed, created or destroyed in the block.
+fn written_accounts(tx_traces: &[HashMap<Address, Account>]) -> HashSet<Address> {
+    tx_traces
+        .iter()
+        .flatten()
+        .filter(|(_, account)| {
+            account.is_created()
+                || account.is_selfdestructed()
+                || account.storage.values().any(|slot| slot.is_changed())
+        })
+        .map(|(address, _)| *address)
+        .collect()
+}
+
+fn state_witness(
+    state: Box<dyn StateProvider>,
+    cache: &mut ProofCache,
+    state_access: HashMap<Address, HashSet<U256>>,
+) -> Result<(
+    HashedPartialTrie,
+    HashMap<primitive_types::H256, HashedPartialTrie>,
+)> {
+    // fetch the state witness
+    let state_access: HashMap<Address, Vec<StorageKey>> = state_access
+        .into_iter()
+        .map(|(k, v)| (k, v.into_iter().map(Into::into).collect()))
+        .collect();
+    let state_access_accts = state_access.keys().cloned().collect::<Vec<_>>();
+    let state_witness = cache.multiproof(state.as_ref(), state_access)?;
+
+    // build the account trie witness
+    let mut state_trie_builder =
+        PartialTrieBuilder::new(state_witness.root.compat(), Default::default());
+    state_trie_builder.insert_proof(
+        state_witness
+            .account_subtree
+            .into_values()
+            .map(Into::into)
+            .collect(),
+    );
+
+    // build the storage trie witnesses
+    let mut storage_witnesses: HashMap<primitive_types::H256, HashedPartialTrie> = state_witness
+        .storages
+        .into_iter()
+        .map(|(hashed_addr, StorageMultiProof { root, subtree })| {
+            let mut storage_trie_builder =
+                PartialTrieBuilder::new(root.compat(), Default::default());
+            storage_trie_builder.insert_proof(subtree.into_values().map(Into::into).collect());
+            (hashed_addr.compat(), storage_trie_builder.build())
+        })
+        .collect();
+
+    for addr in state_access_accts {
+        let hashed_addr = keccak256(addr);
+        storage_witnesses.entry(hashed_addr.compat()).or_default();
+    }
+
+    Ok((state_trie_builder.build(), storage_witnesses))
+}
+
+fn separate_pre_images(
+    state_trie: HashedPartialTrie,
+    storage_tries: HashMap<primitive_types::H256, HashedPartialTrie>,
+) -> BlockTraceTriePreImages {
+    BlockTraceTriePreImages::Separate(SeparateTriePreImages {
+        state: SeparateTriePreImage::Direct(state_trie),
+        storage: SeparateStorageTriesPreImage::MultipleTries(
+            storage_tries
+                .into_iter()
+                .map(|(hashed_addr, trie)| (hashed_addr, SeparateTriePreImage::Direct(trie)))
+                .collect(),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpt_trie::{nibbles::Nibbles, partial_trie::PartialTrie};
+    use reth_primitives::{
+        proofs::calculate_transaction_root, Address, Block, Bytes, Header, Log, Signature,
+        Transaction, TxEip1559, TxEip4844,
+    };
+    use revm::primitives::{AccountInfo, AccountStatus, Bytecode, EvmStorageSlot};
+
+    const CODE: &[u8] = &[0x60, 0x00, 0x60, 0x00, 0xf3];
+
+    fn blob_transaction() -> TransactionSigned {
+        TransactionSigned::from_transaction_and_signature(
+            Transaction::Eip4844(TxEip4844 {
+                chain_id: 1337,
+                nonce: 1,
+                gas_limit: 21_000,
+                max_fee_per_gas: 10,
+                max_priority_fee_per_gas: 1,
+                max_fee_per_blob_gas: 1,
+                to: Address::repeat_byte(0x11),
+                blob_versioned_hashes: vec![B256::repeat_byte(0x01); 2],
+                ..Default::default()
+            }),
+            Signature::default(),
+        )
+    }
+
+    fn dynamic_fee_transaction() -> TransactionSigned {
+        TransactionSigned::from_transaction_and_signature(
+            Transaction::Eip1559(TxEip1559 {
+                chain_id: 1337,
+                nonce: 0,
+                gas_limit: 21_000,
+                max_fee_per_gas: 10,
+                max_priority_fee_per_gas: 1,
+                ..Default::default()
+            }),
+            Signature::default(),
+        )
+    }
+
+    fn receipt(tx_type: TxType, cumulative_gas_used: u64) -> Receipt {
+        Receipt {
+            tx_type,
+            success: true,
+            cumulative_gas_used,
+            logs: vec![Log::new_unchecked(
+                Address::repeat_byte(0x11),
+                vec![B256::repeat_byte(0x02)],
+                Bytes::from_static(&[0x03]),
+            )],
+        }
+    }
+
+    /// Build a trie keyed by the RLP encoded index, as the transactions and
+    /// receipts tries are.
+    fn index_trie(values: impl IntoIterator<Item = Vec<u8>>) -> HashedPartialTrie {
+        let mut trie = HashedPartialTrie::default();
+        for (index, value) in values.into_iter().enumerate() {
+            let key = Nibbles::from_bytes_be(&alloy_rlp::encode(index)).expect("index is a key");
+            trie.insert(key, value).expect("trie is fully revealed");
+        }
+        trie
+    }
+
+    #[test]
+    fn blob_transaction_bytes_match_transactions_root() {
+        let transactions = vec![dynamic_fee_transaction(), blob_transaction()];
+        let bytes: Vec<_> = transactions.iter().map(transaction_bytes).collect();
+
+        assert_eq!(bytes[1][0], TxType::Eip4844 as u8);
+        assert_eq!(
+            index_trie(bytes).hash(),
+            calculate_transaction_root(&transactions).compat()
+        );
+    }
+
+    #[test]
+    fn blob_receipt_bytes_match_receipts_root() {
+        let receipts = vec![
+            receipt(TxType::Eip1559, 21_000),
+            receipt(TxType::Eip4844, 42_000),
+        ];
+
+        // the prover unwraps the RLP string around typed receipts before
+        // inserting them into the receipts trie
+        let leaves = receipts.iter().cloned().map(|receipt| {
+            let bytes = receipt_bytes(receipt);
+            let mut payload = bytes.as_slice();
+            let header = alloy_rlp::Header::decode(&mut payload).expect("typed receipt");
+            assert!(!header.list);
+            payload.to_vec()
+        });
+        let leaves: Vec<_> = leaves.collect();
+
+        assert_eq!(leaves[1][0], TxType::Eip4844 as u8);
+        assert_eq!(
+            index_trie(leaves).hash(),
+            calculate_receipt_root(
+                &receipts
+                    .into_iter()
+                    .map(Receipt::with_bloom)
+                    .collect::<Vec<_>>()
+            )
+            .compat()
+        );
+    }
+
+    /// A block of `receipts.len()` transactions committing to `receipts`.
+    fn block_with_receipts(receipts: &[Receipt]) -> SealedBlockWithSenders {
+        let block = Block {
+            header: Header {
+                gas_used: receipts
+                    .last()
+                    .map_or(0, |receipt| receipt.cumulative_gas_used),
+                receipts_root: calculate_receipt_root(
+                    &receipts
+                        .iter()
+                        .cloned()
+                        .map(Receipt::with_bloom)
+                        .collect::<Vec<_>>(),
+                ),
+                ..Default::default()
+            },
+            body: vec![dynamic_fee_transaction(); receipts.len()],
+            ..Default::default()
+        };
+        SealedBlockWithSenders::new(block.seal_slow(), vec![Address::ZERO; receipts.len()])
+            .expect("one sender per transaction")
+    }
+
+    fn check(block: &SealedBlockWithSenders, receipts: Vec<Receipt>) -> Result<(), TracerError> {
+        let tx_traces = vec![HashMap::new(); receipts.len()];
+        check_receipts(block, receipts.into_iter().map(Some).collect(), &tx_traces).map(|_| ())
+    }
+
+    #[test]
+    fn receipts_matching_the_header_are_accepted() {
+        let receipts = vec![
+            receipt(TxType::Eip1559, 21_000),
+            receipt(TxType::Eip1559, 42_000),
+        ];
+        let block = block_with_receipts(&receipts);
+
+        assert!(check(&block, receipts).is_ok());
+    }
+
+    #[test]
+    fn receipt_with_mismatched_status_is_rejected() {
+        let mut receipts = vec![
+            receipt(TxType::Eip1559, 21_000),
+            receipt(TxType::Eip1559, 42_000),
+        ];
+        let block = block_with_receipts(&receipts);
+        receipts[1].success = false;
+
+        assert!(matches!(
+            check(&block, receipts),
+            Err(TracerError::ReceiptsRootMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn receipt_with_mismatched_cumulative_gas_is_rejected() {
+        let mut receipts = vec![
+            receipt(TxType::Eip1559, 21_000),
+            receipt(TxType::Eip1559, 42_000),
+        ];
+        let block = block_with_receipts(&receipts);
+
+        receipts[1].cumulative_gas_used = 40_000;
+        assert!(matches!(
+            check(&block, receipts.clone()),
+            Err(TracerError::GasUsedMismatch {
+                header: 42_000,
+                receipts: 40_000
+            })
+        ));
+
+        receipts[1].cumulative_gas_used = 20_000;
+        assert!(matches!(
+            check(&block, receipts),
+            Err(TracerError::CumulativeGasDecreased { index: 1, .. })
+        ));
+    }
+
+    fn created_account(
+        status: AccountStatus,
+        storage: impl IntoIterator<Item = (u64, EvmStorageSlot)>,
+    ) -> Account {
+        let code = Bytes::from_static(CODE);
+        Account {
+            info: AccountInfo::new(U256::from(5), 1, keccak256(&code), Bytecode::new_raw(code)),
+            storage: storage
+                .into_iter()
+                .map(|(slot, value)| (U256::from(slot), value))
+                .collect(),
+            status: AccountStatus::Touched | AccountStatus::Created | status,
+        }
+    }
+
+    fn trace_txs(tx_traces: &[HashMap<Address, Account>]) -> Vec<TxnInfo> {
+        trace_txs_at(tx_traces, true)
+    }
+
+    fn trace_txs_at(tx_traces: &[HashMap<Address, Account>], cancun: bool) -> Vec<TxnInfo> {
+        let mut code_db = HashMap::new();
+        let mut cum_gas = 0;
+        tx_traces
+            .iter()
+            .enumerate()
+            .map(|(index, tx_trace)| {
+                trace_transaction(
+                    &dynamic_fee_transaction(),
+                    receipt(TxType::Eip1559, 21_000 * (index as u64 + 1)),
+                    tx_trace,
+                    &mut code_db,
+                    &mut cum_gas,
+                    cancun,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn account_destroyed_in_creating_transaction_is_cleared() {
+        let contract = Address::repeat_byte(0x11);
+        let tx_traces = vec![HashMap::from([(
+            contract,
+            created_account(
+                AccountStatus::SelfDestructed,
+                [(1, EvmStorageSlot::new_changed(U256::ZERO, U256::from(9)))],
+            ),
+        )])];
+
+        let trace = &trace_txs(&tx_traces)[0].traces[&contract.compat()];
+        assert_eq!(trace.balance, Some(0.into()));
+        assert_eq!(trace.nonce, Some(0.into()));
+        assert_eq!(trace.self_destructed, Some(true));
+        // writes made before the destruction do not outlive the transaction
+        assert!(trace.storage_written.is_none());
+        assert!(trace.storage_read.is_none());
+        assert!(trace.code_usage.is_none());
+        assert!(written_accounts(&tx_traces).contains(&contract));
+    }
+
+    #[test]
+    fn existing_account_destroyed_before_cancun_keeps_its_reads() {
+        let contract = Address::repeat_byte(0x11);
+        let code = Bytes::from_static(CODE);
+        let tx_traces = vec![HashMap::from([(
+            contract,
+            Account {
+                info: AccountInfo::new(U256::from(5), 1, keccak256(&code), Bytecode::new_raw(code)),
+                storage: HashMap::from([(U256::from(1), EvmStorageSlot::new(U256::from(9)))]),
+                status: AccountStatus::Touched | AccountStatus::SelfDestructed,
+            },
+        )])];
+
+        let trace = &trace_txs_at(&tx_traces, false)[0].traces[&contract.compat()];
+        assert_eq!(trace.self_destructed, Some(true));
+        // the slot loaded before the destruction is part of the witness
+        assert_eq!(
+            trace.storage_read,
+            Some(vec![B256::from(U256::from(1)).compat()])
+        );
+        assert!(matches!(
+            trace.code_usage,
+            Some(ContractCodeUsage::Read(hash)) if hash == keccak256(CODE).compat()
+        ));
+    }
+
+    #[test]
+    fn account_recreated_in_later_transaction_is_written_fresh() {
+        let contract = Address::repeat_byte(0x11);
+        let tx_traces = vec![
+            HashMap::from([(
+                contract,
+                created_account(
+                    AccountStatus::SelfDestructed,
+                    [(1, EvmStorageSlot::new_changed(U256::ZERO, U256::from(9)))],
+                ),
+            )]),
+            HashMap::from([(
+                contract,
+                created_account(
+                    AccountStatus::empty(),
+                    [
+                        (1, EvmStorageSlot::new_changed(U256::ZERO, U256::from(3))),
+                        (2, EvmStorageSlot::new_changed(U256::from(9), U256::ZERO)),
+                    ],
+                ),
+            )]),
+        ];
+
+        let txn_infos = trace_txs(&tx_traces);
+        assert_eq!(
+            txn_infos[0].traces[&contract.compat()].self_destructed,
+            Some(true)
+        );
+
+        let trace = &txn_infos[1].traces[&contract.compat()];
+        assert_eq!(trace.self_destructed, None);
+        assert_eq!(trace.balance, Some(5.into()));
+        assert_eq!(trace.nonce, Some(1.into()));
+        assert!(matches!(
+            &trace.code_usage,
+            Some(ContractCodeUsage::Write(code)) if code.as_slice() == CODE
+        ));
+        // the zero write to slot 2 does not touch the fresh storage trie
+        assert_eq!(
+            trace.storage_written,
+            Some(HashMap::from([(
+                B256::from(U256::from(1)).compat(),
+                U256::from(3).compat()
+            )]))
+        );
+        assert!(written_accounts(&tx_traces).contains(&contract));
+    }
+}
@@ -0,0 +1,160 @@
+//! Post-block state changes.
+//!
+//! Withdrawals, and block and ommer rewards on proof of work chains, are
+//! credited after the last transaction of a block and are not part of any
+//! transaction trace. Withdrawals are applied by the prover from the block
+//! data, so their recipients are only added to the state witness. Rewards are
+//! captured in a synthetic trace entry which carries no transaction payload.
+use compat::Compat;
+use eyre::Result;
+use reth_consensus_common::calc::{base_block_reward, block_reward, ommer_reward};
+use reth_primitives::{constants::GWEI_TO_WEI, ChainSpec, Header, Withdrawals};
+use reth_provider::StateProvider;
+use revm::primitives::{Account, Address, U256};
+use std::collections::{HashMap, HashSet};
+use trace_decoder::{TxnInfo, TxnMeta, TxnTrace};
+
+/// Balance increments applied after the last transaction of a block.
+#[derive(Debug, Default)]
+pub(crate) struct PostBlockState {
+    increments: HashMap<Address, U256>,
+}
+
+impl PostBlockState {
+    /// Credit `amount` wei to `address`.
+    pub(crate) fn credit(&mut self, address: Address, amount: U256) {
+        *self.increments.entry(address).or_default() += amount;
+    }
+
+    /// Credit the block reward to the beneficiary and the ommer rewards to the
+    /// ommer beneficiaries. The beneficiary is always included in the witness,
+    /// as it receives the priority fees even without a block reward.
+    pub(crate) fn credit_block_rewards(
+        &mut self,
+        chain_spec: &ChainSpec,
+        header: &Header,
+        ommers: &[Header],
+        total_difficulty: U256,
+    ) {
+        self.credit(header.beneficiary, U256::ZERO);
+        let Some(base_reward) = base_block_reward(
+            chain_spec,
+            header.number,
+            header.difficulty,
+            total_difficulty,
+        ) else {
+            return;
+        };
+
+        self.credit(
+            header.beneficiary,
+            U256::from(block_reward(base_reward, ommers.len())),
+        );
+        for ommer in ommers {
+            self.credit(
+                ommer.beneficiary,
+                U256::from(ommer_reward(base_reward, header.number, ommer.number)),
+            );
+        }
+    }
+
+    /// Build the synthetic trace entry holding the final balances of the
+    /// rewarded accounts, recording them in `state_access` so they are
+    /// included in the witness.
+    ///
+    /// Returns `None` if there is no post block state change.
+    pub(crate) fn into_trace(
+        self,
+        state: &dyn StateProvider,
+        tx_traces: &[HashMap<Address, Account>],
+        state_access: &mut HashMap<Address, HashSet<U256>>,
+    ) -> Result<Option<TxnInfo>> {
+        let mut traces = HashMap::new();
+        for (address, increment) in self.increments {
+            state_access.entry(address).or_default();
+            if increment.is_zero() {
+                continue;
+            }
+
+            // the balance after the last transaction touching the account, or
+            // the balance at the parent block if no transaction touched it
+            let balance = match tx_traces
+                .iter()
+                .rev()
+                .find_map(|tx_trace| tx_trace.get(&address).filter(|acc| acc.is_touched()))
+            {
+                Some(account) => Some(account.info.balance),
+                None => state.basic_account(address)?.map(|account| account.balance),
+            };
+
+            let trace = TxnTrace {
+                balance: Some((balance.unwrap_or_default() + increment).compat()),
+                nonce: None,
+                storage_read: None,
+                storage_written: None,
+                code_usage: None,
+                self_destructed: None,
+            };
+            traces.insert(address.compat(), trace);
+        }
+
+        if traces.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(TxnInfo {
+            meta: TxnMeta {
+                byte_code: vec![],
+                new_receipt_trie_node_byte: vec![],
+                gas_used: 0,
+            },
+            traces,
+        }))
+    }
+}
+
+/// The non-zero withdrawals of a block as recipient and amount in wei.
+pub(crate) fn withdrawals(withdrawals: Option<&Withdrawals>) -> Vec<(Address, U256)> {
+    withdrawals
+        .iter()
+        .flat_map(|withdrawals| withdrawals.iter())
+        .filter(|withdrawal| withdrawal.amount > 0)
+        .map(|withdrawal| {
+            (
+                withdrawal.address,
+                U256::from(withdrawal.amount) * U256::from(GWEI_TO_WEI),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth_primitives::MAINNET;
+    use reth_provider::test_utils::NoopProvider;
+
+    #[test]
+    fn post_merge_beneficiary_is_in_the_witness() {
+        let beneficiary = Address::repeat_byte(0x11);
+        let header = Header {
+            number: 20_000_000,
+            beneficiary,
+            ..Default::default()
+        };
+        let total_difficulty = MAINNET
+            .get_final_paris_total_difficulty()
+            .expect("mainnet has merged");
+
+        let mut post_block_state = PostBlockState::default();
+        post_block_state.credit_block_rewards(&MAINNET, &header, &[], total_difficulty);
+        let mut state_access = HashMap::new();
+        let trace = post_block_state
+            .into_trace(&NoopProvider::default(), &[], &mut state_access)
+            .expect("no state is read");
+
+        // no reward is credited, so there is no synthetic trace entry
+        assert!(trace.is_none());
+        assert!(state_access.contains_key(&beneficiary));
+    }
+}
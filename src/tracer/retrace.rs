@@ -0,0 +1,71 @@
+//! Re-tracing of already imported blocks.
+//!
+//! The block is re-executed on its parent state to reproduce the execution
+//! traces the ExEx receives with each committed chain, and then traced the
+//! same way as a newly committed block.
+use eyre::{eyre, Result};
+use reth_evm::execute::{BatchExecutor, BlockExecutionInput, BlockExecutorProvider};
+use reth_node_ethereum::EthExecutorProvider;
+use reth_primitives::{TransactionVariant, B256};
+use reth_provider::{BlockReader, ChainSpecProvider, StateProviderFactory};
+use reth_revm::database::StateProviderDatabase;
+use trace_decoder::BlockTrace;
+
+use super::{trace_block, ProofCache, TraceContext, TracerConfig};
+
+/// Re-execute and trace the canonical block `number`, returning its hash and
+/// trace.
+pub(crate) fn retrace_block<P>(
+    provider: &P,
+    config: &TracerConfig,
+    number: u64,
+) -> Result<(B256, BlockTrace)>
+where
+    P: BlockReader + StateProviderFactory + ChainSpecProvider,
+{
+    let block = provider
+        .sealed_block_with_senders(number.into(), TransactionVariant::WithHash)?
+        .ok_or_else(|| eyre!("block {number} not found"))?;
+    let total_difficulty = provider
+        .header_td_by_number(number)?
+        .ok_or_else(|| eyre!("total difficulty of block {number} not found"))?;
+    let chain_spec = provider.chain_spec();
+
+    let state = provider.state_by_block_hash(block.parent_hash)?;
+    let mut executor = EthExecutorProvider::ethereum(chain_spec.clone())
+        .batch_executor(StateProviderDatabase::new(state));
+    executor.execute_and_verify_one(BlockExecutionInput::new(
+        &block.clone().unseal(),
+        total_difficulty,
+    ))?;
+    let outcome = executor.finalize();
+
+    let receipts = outcome.receipts_by_block(number).to_vec();
+    let trace = outcome
+        .traces
+        .into_iter()
+        .next()
+        .ok_or_else(|| eyre!("execution trace of block {number} missing"))?;
+    let tx_traces = outcome
+        .tx_traces
+        .into_iter()
+        .next()
+        .ok_or_else(|| eyre!("transaction traces of block {number} missing"))?;
+
+    // the proof cache follows the tip, historical state is always fetched
+    let mut cache = ProofCache::new(0);
+    let block_hash = block.hash();
+    let block_trace = trace_block(
+        TraceContext {
+            provider,
+            chain_spec: &chain_spec,
+            config,
+            cache: &mut cache,
+        },
+        block,
+        receipts,
+        trace,
+        tx_traces,
+    )?;
+    Ok((block_hash, block_trace))
+}
@@ -0,0 +1,268 @@
+//! Pre-block system calls.
+//!
+//! Since Cancun the parent beacon block root is written to the EIP-4788
+//! beacon roots contract, and since Prague the parent block hash is written to
+//! the EIP-2935 history storage contract, before any transaction is executed.
+//! These writes are not part of any transaction trace, so they are captured
+//! in a dedicated synthetic trace entry which carries no transaction payload.
+use compat::Compat;
+use eyre::Result;
+use reth_primitives::{address, ChainSpec, Header, B256};
+use reth_provider::StateProvider;
+use revm::primitives::{Address, BLOCKHASH_SERVE_WINDOW, BLOCKHASH_STORAGE_ADDRESS, U256};
+use std::collections::{HashMap, HashSet};
+use trace_decoder::{ContractCodeUsage, TxnInfo, TxnMeta, TxnTrace};
+
+/// The EIP-4788 beacon roots contract address.
+const BEACON_ROOTS_ADDRESS: Address = address!("000F3df6D732807Ef1319fB7B8bB8522d0Beac02");
+
+/// The EIP-4788 ring buffer length.
+const HISTORY_BUFFER_LENGTH: u64 = 8191;
+
+/// Build the synthetic trace entry for the pre-block system calls, recording
+/// the accessed slots in `state_access` so they are included in the witness.
+///
+/// Returns `None` if no system call is active for the block.
+pub(crate) fn system_call_trace(
+    chain_spec: &ChainSpec,
+    header: &Header,
+    state: &dyn StateProvider,
+    code_db: &mut HashMap<primitive_types::H256, Vec<u8>>,
+    state_access: &mut HashMap<Address, HashSet<U256>>,
+) -> Result<Option<TxnInfo>> {
+    let mut writes: Vec<(Address, Vec<(U256, U256)>)> = vec![];
+
+    if header.number > 0 && chain_spec.is_cancun_active_at_timestamp(header.timestamp) {
+        if let Some(parent_beacon_block_root) = header.parent_beacon_block_root {
+            let timestamp_index = header.timestamp % HISTORY_BUFFER_LENGTH;
+            let root_index = timestamp_index + HISTORY_BUFFER_LENGTH;
+            writes.push((
+                BEACON_ROOTS_ADDRESS,
+                vec![
+                    (U256::from(timestamp_index), U256::from(header.timestamp)),
+                    (
+                        U256::from(root_index),
+                        b256_to_u256(parent_beacon_block_root),
+                    ),
+                ],
+            ));
+        }
+    }
+
+    if header.number > 0 && chain_spec.is_prague_active_at_timestamp(header.timestamp) {
+        let slot = (header.number - 1) % BLOCKHASH_SERVE_WINDOW as u64;
+        writes.push((
+            BLOCKHASH_STORAGE_ADDRESS,
+            vec![(U256::from(slot), b256_to_u256(header.parent_hash))],
+        ));
+    }
+
+    let mut traces = HashMap::new();
+    for (address, slots) in writes {
+        // the system call is skipped if the contract is not deployed
+        let Some(code) = state.account_code(address)? else {
+            continue;
+        };
+        let code_hash = code.hash_slow();
+        code_db.insert(code_hash.compat(), code.original_bytes().to_vec());
+
+        state_access
+            .entry(address)
+            .or_default()
+            .extend(slots.iter().map(|(slot, _)| *slot));
+
+        let trace = TxnTrace {
+            balance: None,
+            nonce: None,
+            storage_read: None,
+            storage_written: Some(
+                slots
+                    .into_iter()
+                    .map(|(slot, value)| (B256::from(slot).compat(), value.compat()))
+                    .collect(),
+            ),
+            code_usage: Some(ContractCodeUsage::Read(code_hash.compat())),
+            self_destructed: None,
+        };
+        traces.insert(address.compat(), trace);
+    }
+
+    if traces.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(TxnInfo {
+        meta: TxnMeta {
+            byte_code: vec![],
+            new_receipt_trie_node_byte: vec![],
+            gas_used: 0,
+        },
+        traces,
+    }))
+}
+
+fn b256_to_u256(value: B256) -> U256 {
+    U256::from_be_bytes(value.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpt_trie::partial_trie::{HashedPartialTrie, PartialTrie};
+    use reth_primitives::{
+        constants::EMPTY_ROOT_HASH,
+        keccak256,
+        proofs::{calculate_receipt_root, calculate_transaction_root},
+        Receipt, Signature, Transaction, TransactionSigned, TxLegacy, TxType, KECCAK_EMPTY,
+    };
+    use trace_decoder::{BlockLevelData, BlockTrace, OtherBlockData};
+
+    use super::super::{
+        receipt_bytes, separate_pre_images, transaction_bytes,
+        trie::{trie_from_entries, TrieAccount},
+    };
+
+    const CODE: &[u8] = &[0x60, 0x00, 0x60, 0x00, 0xf3];
+
+    fn account(nonce: u64, balance: u64, code_hash: B256) -> Vec<u8> {
+        TrieAccount {
+            nonce,
+            balance: U256::from(balance),
+            storage_root: EMPTY_ROOT_HASH,
+            code_hash,
+        }
+        .encode()
+    }
+
+    /// An entry without transaction payload, as emitted for the pre-block
+    /// system calls and the post-block rewards.
+    fn payload_less(traces: HashMap<primitive_types::H160, TxnTrace>) -> TxnInfo {
+        TxnInfo {
+            meta: TxnMeta {
+                byte_code: vec![],
+                new_receipt_trie_node_byte: vec![],
+                gas_used: 0,
+            },
+            traces,
+        }
+    }
+
+    #[test]
+    fn payload_less_entries_do_not_shift_transaction_indices() {
+        let sender = Address::repeat_byte(0x22);
+        let beneficiary = Address::repeat_byte(0x33);
+        let state = trie_from_entries([
+            (
+                keccak256(BEACON_ROOTS_ADDRESS).compat(),
+                account(1, 0, keccak256(CODE)),
+            ),
+            (
+                keccak256(sender).compat(),
+                account(0, 1_000_000, KECCAK_EMPTY),
+            ),
+            (keccak256(beneficiary).compat(), account(0, 0, KECCAK_EMPTY)),
+        ]);
+        let storage = HashMap::from([(
+            keccak256(BEACON_ROOTS_ADDRESS).compat(),
+            HashedPartialTrie::default(),
+        )]);
+
+        let tx = TransactionSigned::from_transaction_and_signature(
+            Transaction::Legacy(TxLegacy {
+                chain_id: Some(1337),
+                nonce: 0,
+                gas_price: 10,
+                gas_limit: 21_000,
+                ..Default::default()
+            }),
+            Signature::default(),
+        );
+        let receipt = Receipt {
+            tx_type: TxType::Legacy,
+            success: true,
+            cumulative_gas_used: 21_000,
+            logs: vec![],
+        };
+
+        let system_call = payload_less(HashMap::from([(
+            BEACON_ROOTS_ADDRESS.compat(),
+            TxnTrace {
+                storage_written: Some(HashMap::from([(
+                    B256::from(U256::from(1)).compat(),
+                    U256::from(2).compat(),
+                )])),
+                code_usage: Some(ContractCodeUsage::Read(keccak256(CODE).compat())),
+                balance: None,
+                nonce: None,
+                storage_read: None,
+                self_destructed: None,
+            },
+        )]));
+        let transaction = TxnInfo {
+            meta: TxnMeta {
+                byte_code: transaction_bytes(&tx),
+                new_receipt_trie_node_byte: receipt_bytes(receipt.clone()),
+                gas_used: 21_000,
+            },
+            traces: HashMap::from([(
+                sender.compat(),
+                TxnTrace {
+                    balance: Some((1_000_000 - 210_000).into()),
+                    nonce: Some(1.into()),
+                    storage_read: None,
+                    storage_written: None,
+                    code_usage: None,
+                    self_destructed: None,
+                },
+            )]),
+        };
+        let post_block = payload_less(HashMap::from([(
+            beneficiary.compat(),
+            TxnTrace {
+                balance: Some(21_000.into()),
+                nonce: None,
+                storage_read: None,
+                storage_written: None,
+                code_usage: None,
+                self_destructed: None,
+            },
+        )]));
+
+        let block_trace = BlockTrace {
+            trie_pre_images: separate_pre_images(state.clone(), storage),
+            code_db: Some(HashMap::from([(keccak256(CODE).compat(), CODE.to_vec())])),
+            txn_info: vec![system_call, transaction, post_block],
+        };
+        let other_data = OtherBlockData {
+            b_data: BlockLevelData {
+                b_meta: evm_arithmetization::proof::BlockMetadata {
+                    block_gas_used: 21_000.into(),
+                    ..Default::default()
+                },
+                b_hashes: Default::default(),
+                withdrawals: vec![],
+            },
+            checkpoint_state_trie_root: state.hash(),
+        };
+        let inputs =
+            trace_decoder::entrypoint(block_trace, other_data, 1).expect("block trace decodes");
+
+        // the transaction is at index 0 of both tries, as in the block
+        let roots = &inputs.last().expect("block has inputs").trie_roots_after;
+        assert_eq!(
+            roots.transactions_root,
+            calculate_transaction_root(&[tx]).compat()
+        );
+        assert_eq!(
+            roots.receipts_root,
+            calculate_receipt_root(&[receipt.with_bloom()]).compat()
+        );
+        assert_eq!(
+            inputs
+                .iter()
+                .filter(|input| input.signed_txn.is_some())
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! Helpers for working with partial tries.
+use alloy_rlp::{Decodable, Encodable, Header};
+use mpt_trie::{
+    nibbles::Nibbles,
+    partial_trie::{HashedPartialTrie, Node},
+};
+use primitive_types::H256;
+use reth_primitives::{keccak256, B256, U256};
+use std::collections::HashMap;
+
+/// The RLP representation of an account stored in the state trie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TrieAccount {
+    pub nonce: u64,
+    pub balance: U256,
+    pub storage_root: B256,
+    pub code_hash: B256,
+}
+
+impl TrieAccount {
+    /// Decode an account from a state trie leaf value.
+    pub(crate) fn decode(mut buf: &[u8]) -> Result<Self, alloy_rlp::Error> {
+        let buf = &mut buf;
+        let header = Header::decode(buf)?;
+        if !header.list {
+            return Err(alloy_rlp::Error::UnexpectedString);
+        }
+        Ok(Self {
+            nonce: u64::decode(buf)?,
+            balance: U256::decode(buf)?,
+            storage_root: B256::decode(buf)?,
+            code_hash: B256::decode(buf)?,
+        })
+    }
+
+    /// Encode the account as a state trie leaf value.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![];
+        self.nonce.encode(&mut payload);
+        self.balance.encode(&mut payload);
+        self.storage_root.encode(&mut payload);
+        self.code_hash.encode(&mut payload);
+
+        let mut out = vec![];
+        Header {
+            list: true,
+            payload_length: payload.len(),
+        }
+        .encode(&mut out);
+        out.extend(payload);
+        out
+    }
+}
+
+/// Expand packed nibbles into one nibble per byte.
+pub(crate) fn nibbles_to_vec(nibbles: &Nibbles) -> Vec<u8> {
+    (0..nibbles.count).map(|i| nibbles.get_nibble(i)).collect()
+}
+
+/// Look up the value stored at `key` (one nibble per byte) in a partial trie.
+///
+/// Returns the hash of the blinded node on the path if the trie does not
+/// reveal enough nodes to resolve the key.
+pub(crate) fn trie_get<'a>(
+    trie: &'a HashedPartialTrie,
+    mut key: &[u8],
+) -> Result<Option<&'a [u8]>, H256> {
+    let mut node = trie;
+    loop {
+        match &**node {
+            Node::Empty => return Ok(None),
+            Node::Hash(hash) => return Err(*hash),
+            Node::Branch { children, value } => match key.split_first() {
+                Some((nibble, rest)) => {
+                    node = children[*nibble as usize].as_ref();
+                    key = rest;
+                }
+                None => return Ok(Some(value.as_slice()).filter(|value| !value.is_empty())),
+            },
+            Node::Extension { nibbles, child } => {
+                let prefix = nibbles_to_vec(nibbles);
+                if !key.starts_with(&prefix) {
+                    return Ok(None);
+                }
+                key = &key[prefix.len()..];
+                node = child.as_ref();
+            }
+            Node::Leaf { nibbles, value } => {
+                return Ok((nibbles_to_vec(nibbles) == key).then_some(value.as_slice()))
+            }
+        }
+    }
+}
+
+/// The RLP encodings of the revealed nodes of a trie which are referenced by
+/// hash, including the root.
+pub(crate) fn trie_nodes(trie: &HashedPartialTrie) -> Vec<Vec<u8>> {
+    let mut nodes = vec![];
+    if !matches!(&**trie, Node::Empty | Node::Hash(_)) {
+        let root = encode_node(trie, &mut nodes);
+        nodes.push(root);
+    }
+    nodes
+}
+
+/// RLP encode a node, collecting the encodings of its hashed descendants.
+fn encode_node(node: &HashedPartialTrie, nodes: &mut Vec<Vec<u8>>) -> Vec<u8> {
+    let mut payload = vec![];
+    match &**node {
+        Node::Empty | Node::Hash(_) => return node_reference(node, nodes),
+        Node::Branch { children, value } => {
+            for child in children.iter() {
+                payload.extend(node_reference(child, nodes));
+            }
+            value.as_slice().encode(&mut payload);
+        }
+        Node::Extension { nibbles, child } => {
+            nibbles
+                .to_hex_prefix_encoding(false)
+                .as_ref()
+                .encode(&mut payload);
+            payload.extend(node_reference(child, nodes));
+        }
+        Node::Leaf { nibbles, value } => {
+            nibbles
+                .to_hex_prefix_encoding(true)
+                .as_ref()
+                .encode(&mut payload);
+            value.as_slice().encode(&mut payload);
+        }
+    }
+
+    let mut out = vec![];
+    Header {
+        list: true,
+        payload_length: payload.len(),
+    }
+    .encode(&mut out);
+    out.extend(payload);
+    out
+}
+
+/// Encode a reference to a child node. Nodes shorter than 32 bytes are
+/// embedded in their parent, all other nodes are referenced by hash.
+fn node_reference(node: &HashedPartialTrie, nodes: &mut Vec<Vec<u8>>) -> Vec<u8> {
+    let mut out = vec![];
+    match &**node {
+        Node::Empty => out.push(alloy_rlp::EMPTY_STRING_CODE),
+        Node::Hash(hash) => hash.as_bytes().encode(&mut out),
+        _ => {
+            let encoded = encode_node(node, nodes);
+            if encoded.len() < 32 {
+                return encoded;
+            }
+            keccak256(&encoded).as_slice().encode(&mut out);
+            nodes.push(encoded);
+        }
+    }
+    out
+}
+
+/// Rebuild a partial trie from its root hash and RLP encoded nodes keyed by
+/// hash, as produced by [`trie_nodes`]. Nodes missing from `nodes` are left
+/// blinded as hash nodes, so a single node map can serve several tries.
+pub(crate) fn trie_from_nodes(
+    root: H256,
+    nodes: &HashMap<H256, Vec<u8>>,
+) -> Result<HashedPartialTrie, alloy_rlp::Error> {
+    match nodes.get(&root) {
+        Some(node) => decode_node(node, nodes),
+        None => Ok(HashedPartialTrie::new(Node::Hash(root))),
+    }
+}
+
+/// Decode an RLP encoded node, resolving hashed children from `nodes`.
+fn decode_node(
+    mut buf: &[u8],
+    nodes: &HashMap<H256, Vec<u8>>,
+) -> Result<HashedPartialTrie, alloy_rlp::Error> {
+    let mut payload = Header::decode_bytes(&mut buf, true)?;
+    let mut items = vec![];
+    while !payload.is_empty() {
+        let start = payload;
+        let header = Header::decode(&mut payload)?;
+        payload = payload
+            .get(header.payload_length..)
+            .ok_or(alloy_rlp::Error::InputTooShort)?;
+        items.push(&start[..start.len() - payload.len()]);
+    }
+
+    let node = match items.as_slice() {
+        [children @ .., value] if children.len() == 16 => Node::Branch {
+            children: children
+                .iter()
+                .map(|child| decode_reference(child, nodes).map(Into::into))
+                .collect::<Result<Vec<_>, _>>()?
+                .try_into()
+                .expect("branch has 16 children"),
+            value: Header::decode_bytes(&mut &value[..], false)?.to_vec(),
+        },
+        [path, child] => {
+            let path = Header::decode_bytes(&mut &path[..], false)?;
+            let leaf = path.first().is_some_and(|flag| flag & 0x20 != 0);
+            let nibbles = Nibbles::from_hex_prefix_encoding(path)
+                .map_err(|_| alloy_rlp::Error::Custom("malformed hex prefix"))?;
+            match leaf {
+                true => Node::Leaf {
+                    nibbles,
+                    value: Header::decode_bytes(&mut &child[..], false)?.to_vec(),
+                },
+                false => Node::Extension {
+                    nibbles,
+                    child: decode_reference(child, nodes)?.into(),
+                },
+            }
+        }
+        _ => return Err(alloy_rlp::Error::Custom("unexpected trie node item count")),
+    };
+    Ok(HashedPartialTrie::new(node))
+}
+
+/// Decode a child reference, which is either empty, a hash or an embedded node.
+fn decode_reference(
+    item: &[u8],
+    nodes: &HashMap<H256, Vec<u8>>,
+) -> Result<HashedPartialTrie, alloy_rlp::Error> {
+    if Header::decode(&mut &item[..])?.list {
+        return decode_node(item, nodes);
+    }
+    match Header::decode_bytes(&mut &item[..], false)? {
+        [] => Ok(HashedPartialTrie::new(Node::Empty)),
+        hash if hash.len() == 32 => trie_from_nodes(H256::from_slice(hash), nodes),
+        _ => Err(alloy_rlp::Error::UnexpectedLength),
+    }
+}
+
+/// Build a fully revealed trie holding `entries`, for tests.
+#[cfg(test)]
+pub(crate) fn trie_from_entries(
+    entries: impl IntoIterator<Item = (H256, Vec<u8>)>,
+) -> HashedPartialTrie {
+    use mpt_trie::partial_trie::PartialTrie;
+
+    let mut trie = HashedPartialTrie::default();
+    for (key, value) in entries {
+        trie.insert(Nibbles::from_h256_be(key), value)
+            .expect("trie is fully revealed");
+    }
+    trie
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compat::Compat;
+    use mpt_trie::{partial_trie::PartialTrie, trie_subsets::create_trie_subset};
+
+    #[test]
+    fn trie_is_rebuilt_from_its_nodes() {
+        let trie = trie_from_entries((0..32u64).map(|slot| {
+            (
+                keccak256(B256::from(U256::from(slot))).compat(),
+                alloy_rlp::encode(U256::from(slot)),
+            )
+        }));
+        let revealed = keccak256(B256::from(U256::from(1))).compat();
+        let trie = create_trie_subset(&trie, [Nibbles::from_h256_be(revealed)])
+            .expect("trie is fully revealed");
+
+        let nodes = trie_nodes(&trie)
+            .into_iter()
+            .map(|node| (keccak256(&node).compat(), node))
+            .collect();
+        let rebuilt = trie_from_nodes(trie.hash(), &nodes).expect("nodes are well formed");
+
+        assert_eq!(rebuilt.hash(), trie.hash());
+        assert_eq!(trie_nodes(&rebuilt), trie_nodes(&trie));
+        assert_eq!(
+            trie_get(&rebuilt, &nibbles_to_vec(&Nibbles::from_h256_be(revealed))),
+            Ok(Some(alloy_rlp::encode(U256::from(1)).as_slice()))
+        );
+    }
+}
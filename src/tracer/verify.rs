@@ -0,0 +1,245 @@
+//! Stateless re-execution of a block against its witness.
+//!
+//! The block is executed on a database built purely from the trie pre-images
+//! and `code_db` of its stored trace. Any state which the witness does not
+//! reveal aborts execution, so a successful run with matching receipts and
+//! post-state root proves the witness is complete.
+use compat::Compat;
+use mpt_trie::{
+    nibbles::Nibbles,
+    partial_trie::{HashedPartialTrie, PartialTrie},
+};
+use reth_evm::execute::{BlockExecutionInput, BlockExecutorProvider, Executor};
+use reth_node_ethereum::EthExecutorProvider;
+use reth_primitives::{
+    constants::EMPTY_ROOT_HASH, keccak256, Receipt, SealedBlockWithSenders, B256,
+};
+use reth_provider::{BlockReader, ChainSpecProvider};
+use revm::{
+    db::{BundleState, DatabaseRef, WrapDatabaseRef},
+    primitives::{AccountInfo, Address, Bytecode, U256},
+};
+use std::collections::{BTreeMap, HashMap};
+use trace_decoder::{
+    BlockTrace, BlockTraceTriePreImages, SeparateStorageTriesPreImage, SeparateTriePreImage,
+    SeparateTriePreImages,
+};
+
+use super::{
+    super::error::VerifyError,
+    trie::{trie_get, TrieAccount},
+};
+
+/// The number of ancestor block hashes accessible through `BLOCKHASH`.
+const BLOCK_HASH_HISTORY: u64 = 256;
+
+/// Re-execute `block` using only the witness in `block_trace`, checking the
+/// receipts and the post-state root.
+pub(crate) fn verify_block_trace<P: BlockReader + ChainSpecProvider>(
+    provider: &P,
+    block: &SealedBlockWithSenders,
+    receipts: &[Receipt],
+    block_trace: &BlockTrace,
+) -> Result<(), VerifyError> {
+    let mut db = WitnessDb::new(block_trace)?;
+    for number in block.number.saturating_sub(BLOCK_HASH_HISTORY)..block.number {
+        if let Some(hash) = provider.block_hash(number)? {
+            db.block_hashes.insert(number, hash);
+        }
+    }
+
+    let total_difficulty = provider
+        .header_td_by_number(block.number)?
+        .ok_or(VerifyError::MissingTotalDifficulty(block.number))?;
+    let output = EthExecutorProvider::ethereum(provider.chain_spec())
+        .executor(WrapDatabaseRef(&db))
+        .execute(BlockExecutionInput::new(
+            &block.clone().unseal(),
+            total_difficulty,
+        ))
+        .map_err(|err| VerifyError::Execution(err.to_string()))?;
+
+    if output.receipts.len() != receipts.len() {
+        return Err(VerifyError::ReceiptCountMismatch {
+            expected: receipts.len(),
+            actual: output.receipts.len(),
+        });
+    }
+    if let Some(idx) = output
+        .receipts
+        .iter()
+        .zip(receipts)
+        .position(|(actual, expected)| actual != expected)
+    {
+        return Err(VerifyError::ReceiptMismatch(idx));
+    }
+
+    db.apply_post_state(&output.state)?;
+    let state_root = db.state.hash().compat();
+    if state_root != block.state_root {
+        return Err(VerifyError::StateRootMismatch {
+            expected: block.state_root,
+            actual: state_root,
+        });
+    }
+    Ok(())
+}
+
+/// A revm database backed by the trie pre-images of a block trace.
+struct WitnessDb {
+    state: HashedPartialTrie,
+    storage: HashMap<primitive_types::H256, HashedPartialTrie>,
+    code: HashMap<B256, Bytecode>,
+    block_hashes: BTreeMap<u64, B256>,
+}
+
+impl WitnessDb {
+    fn new(block_trace: &BlockTrace) -> Result<Self, VerifyError> {
+        let BlockTraceTriePreImages::Separate(SeparateTriePreImages {
+            state: SeparateTriePreImage::Direct(state),
+            storage: SeparateStorageTriesPreImage::MultipleTries(storage),
+        }) = &block_trace.trie_pre_images
+        else {
+            return Err(VerifyError::UnsupportedPreImages);
+        };
+
+        let storage = storage
+            .iter()
+            .map(|(hashed_addr, trie)| match trie {
+                SeparateTriePreImage::Direct(trie) => Ok((*hashed_addr, trie.clone())),
+                #[allow(unreachable_patterns)]
+                _ => Err(VerifyError::UnsupportedPreImages),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let code = block_trace
+            .code_db
+            .iter()
+            .flatten()
+            .map(|(hash, code)| (hash.compat(), Bytecode::new_raw(code.clone().into())))
+            .collect();
+
+        Ok(Self {
+            state: state.clone(),
+            storage,
+            code,
+            block_hashes: BTreeMap::new(),
+        })
+    }
+
+    fn account(&self, hashed_address: B256) -> Result<Option<TrieAccount>, VerifyError> {
+        trie_get(&self.state, &key_nibbles(hashed_address))
+            .map_err(VerifyError::MissingNode)?
+            .map(TrieAccount::decode)
+            .transpose()
+            .map_err(VerifyError::Rlp)
+    }
+
+    /// Apply the post-block state to the tries.
+    fn apply_post_state(&mut self, bundle: &BundleState) -> Result<(), VerifyError> {
+        for (address, account) in bundle.state() {
+            let hashed_address = keccak256(address);
+            let key = Nibbles::from_h256_be(hashed_address.compat());
+            let Some(info) = &account.info else {
+                self.state.delete(key).map_err(incomplete_witness)?;
+                continue;
+            };
+
+            let mut storage_root = self
+                .account(hashed_address)?
+                .map(|account| account.storage_root)
+                .unwrap_or(EMPTY_ROOT_HASH);
+            let destroyed = account.was_destroyed();
+            let changed_slots: Vec<_> = account
+                .storage
+                .iter()
+                .filter(|(_, slot)| destroyed || slot.is_changed())
+                .collect();
+
+            if destroyed || !changed_slots.is_empty() {
+                let storage_trie = self.storage.entry(hashed_address.compat()).or_default();
+                if destroyed {
+                    *storage_trie = HashedPartialTrie::default();
+                } else if storage_trie.hash().compat() != storage_root {
+                    return Err(VerifyError::MissingStorageTrie(*address));
+                }
+
+                for (slot, value) in changed_slots {
+                    let key = Nibbles::from_h256_be(keccak256(B256::from(*slot)).compat());
+                    if value.present_value.is_zero() {
+                        storage_trie.delete(key).map_err(incomplete_witness)?;
+                    } else {
+                        storage_trie
+                            .insert(key, alloy_rlp::encode(value.present_value))
+                            .map_err(incomplete_witness)?;
+                    }
+                }
+                storage_root = storage_trie.hash().compat();
+            }
+
+            let account = TrieAccount {
+                nonce: info.nonce,
+                balance: info.balance,
+                storage_root,
+                code_hash: info.code_hash,
+            };
+            self.state
+                .insert(key, account.encode())
+                .map_err(incomplete_witness)?;
+        }
+        Ok(())
+    }
+}
+
+impl DatabaseRef for WitnessDb {
+    type Error = VerifyError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        Ok(self
+            .account(keccak256(address))?
+            .map(|account| AccountInfo {
+                balance: account.balance,
+                nonce: account.nonce,
+                code_hash: account.code_hash,
+                code: None,
+            }))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.code
+            .get(&code_hash)
+            .cloned()
+            .ok_or(VerifyError::MissingCode(code_hash))
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let storage = self
+            .storage
+            .get(&keccak256(address).compat())
+            .ok_or(VerifyError::MissingStorageTrie(address))?;
+        let value = trie_get(storage, &key_nibbles(keccak256(B256::from(index))))
+            .map_err(VerifyError::MissingNode)?;
+        match value {
+            Some(mut value) => Ok(alloy_rlp::Decodable::decode(&mut value)?),
+            None => Ok(U256::ZERO),
+        }
+    }
+
+    fn block_hash_ref(&self, number: U256) -> Result<B256, Self::Error> {
+        let number = number.to::<u64>();
+        self.block_hashes
+            .get(&number)
+            .copied()
+            .ok_or(VerifyError::MissingBlockHash(number))
+    }
+}
+
+fn key_nibbles(key: B256) -> Vec<u8> {
+    key.iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+fn incomplete_witness(err: mpt_trie::trie_ops::TrieOpError) -> VerifyError {
+    VerifyError::IncompleteWitness(err.to_string())
+}